@@ -0,0 +1,77 @@
+//! `const fn` bounds for sizing buffers at compile time.
+//!
+//! ```
+//! use embedded_flac_enc::{budget, encoder::EncoderWorkspace, BufferByteSink};
+//!
+//! const FRAME: usize = budget::max_frame_size::<2, 4096>(24);
+//! static mut WORKSPACE: EncoderWorkspace<4096> = EncoderWorkspace::new();
+//! let sink: BufferByteSink<{ budget::sink_buffer_size::<2, 4096>(24, 4) }> = BufferByteSink::new();
+//! assert_eq!(budget::workspace_bytes::<4096>(), core::mem::size_of::<EncoderWorkspace<4096>>());
+//! ```
+
+use crate::encoder::EncoderWorkspace;
+
+/// Sync code, block size and sample rate codes, a 7-byte coded number, 16-bit
+/// uncommon block size and sample rate, and the CRC-8.
+pub const MAX_FRAME_HEADER_SIZE: usize = 16;
+
+/// Worst-case size in bytes of a frame of `CHANNELS` channels of
+/// `BLOCK_SIZE` samples, CRC-16 included.
+///
+/// [`Encoder`](crate::encoder::Encoder) never writes a subframe bigger than
+/// verbatim, so this bounds every frame it produces. Stereo frames are
+/// assumed to carry a side channel, which needs one extra bit per sample.
+pub const fn max_frame_size<const CHANNELS: usize, const BLOCK_SIZE: usize>(
+    bit_depth: u8,
+) -> usize {
+    let side_channel_bits = if CHANNELS == 2 { BLOCK_SIZE } else { 0 };
+    let sub_frame_bits = CHANNELS * (8 + BLOCK_SIZE * bit_depth as usize) + side_channel_bits;
+    MAX_FRAME_HEADER_SIZE + sub_frame_bits.div_ceil(8) + 2
+}
+
+/// RAM taken by the [`EncoderWorkspace`] an encoder of `BLOCK_SIZE` needs.
+pub const fn workspace_bytes<const BLOCK_SIZE: usize>() -> usize {
+    core::mem::size_of::<EncoderWorkspace<BLOCK_SIZE>>()
+}
+
+/// RAM taken by the [`VerifyWorkspace`](crate::encoder::VerifyWorkspace)
+/// verify mode needs, on top of a frame buffer of [`max_frame_size`] bytes.
+#[cfg(feature = "verify")]
+pub const fn verify_workspace_bytes<const CHANNELS: usize, const BLOCK_SIZE: usize>() -> usize {
+    core::mem::size_of::<crate::encoder::VerifyWorkspace<CHANNELS, BLOCK_SIZE>>()
+}
+
+/// Bytes a sink needs to hold `frames` worst-case frames.
+pub const fn sink_buffer_size<const CHANNELS: usize, const BLOCK_SIZE: usize>(
+    bit_depth: u8,
+    frames: usize,
+) -> usize {
+    frames * max_frame_size::<CHANNELS, BLOCK_SIZE>(bit_depth)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{max_frame_size, sink_buffer_size};
+    use crate::{
+        encoder::{Encoder, EncoderWorkspace},
+        BufferByteSink,
+    };
+
+    #[test]
+    fn noise_fits_in_budget() {
+        const FRAME: usize = max_frame_size::<2, 192>(16);
+        assert_eq!(FRAME, 16 + (2 * (8 + 192 * 16) + 192) / 8 + 2);
+
+        let mut seed: u32 = 1;
+        let mut noise = [[0_i16; 192]; 2];
+        for sample in noise.iter_mut().flatten() {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            *sample = (seed >> 16) as i16;
+        }
+        let mut workspace: EncoderWorkspace<192> = EncoderWorkspace::new();
+        let mut encoder: Encoder<2, 192> = Encoder::new(&mut workspace, 44_100, 16).unwrap();
+        let mut sink: BufferByteSink<{ sink_buffer_size::<2, 192>(16, 1) }> = BufferByteSink::new();
+        encoder.encode(&mut sink, [&noise[0], &noise[1]]).unwrap();
+        assert!(sink.as_slice().len() <= FRAME);
+    }
+}
@@ -0,0 +1,253 @@
+use super::Counter;
+use crate::{
+    metadata::{stream_info::StreamInfo, MetaDataBlockType, FLAC_MARKER},
+    ByteSink, Error, SeekableByteSink,
+};
+
+const DESC_LENGTH: u64 = 32;
+
+/// Size and duration, in samples per channel, of one frame in the data
+/// chunk.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CafPacket {
+    pub size: u32,
+    pub frames: u32,
+}
+
+/// Writes a Core Audio Format file holding FLAC frames.
+///
+/// The magic cookie holds the `fLaC` marker and the metadata blocks. Frame
+/// sizes are kept in `packets` until [`finish`](Self::finish) writes the
+/// packet table after the data and fills in what was not known up front.
+pub struct CafWriter<'a> {
+    packets: &'a mut [CafPacket],
+    count: usize,
+    stream_info: Option<StreamInfo>,
+    desc_position: u64,
+    kuki_position: u64,
+    data_position: u64,
+}
+
+impl<'a> CafWriter<'a> {
+    pub fn new(packets: &'a mut [CafPacket]) -> Self {
+        Self {
+            packets,
+            count: 0,
+            stream_info: None,
+            desc_position: 0,
+            kuki_position: 0,
+            data_position: 0,
+        }
+    }
+
+    /// Writes the file header, `desc`, `kuki` and opens `data`.
+    pub fn write_header<S: SeekableByteSink>(
+        &mut self,
+        sink: &mut S,
+        stream_info: &StreamInfo,
+        blocks: &[MetaDataBlockType<'_>],
+    ) -> Result<(), Error> {
+        for block in blocks {
+            block.check_len()?;
+        }
+        self.stream_info = Some(*stream_info);
+        sink.write_bytes(b"caff");
+        sink.write_bytes(&[0, 1, 0, 0]);
+        self.desc_position = sink.position();
+        self.write_desc(sink);
+        self.kuki_position = sink.position();
+        let stream_info = MetaDataBlockType::StreamInfo(*stream_info);
+        let length = FLAC_MARKER.len()
+            + 4
+            + stream_info.len()
+            + blocks.iter().map(|block| 4 + block.len()).sum::<usize>();
+        write_chunk_header(sink, b"kuki", length as u64);
+        sink.write_bytes(&FLAC_MARKER);
+        let mut blocks = core::iter::once(&stream_info).chain(blocks).peekable();
+        while let Some(block) = blocks.next() {
+            block.write_block(blocks.peek().is_none(), sink)?;
+        }
+        self.data_position = sink.position();
+        // Filled in by `finish`.
+        write_chunk_header(sink, b"data", 0);
+        // Edit count.
+        sink.write_bytes(&0u32.to_be_bytes());
+        Ok(())
+    }
+
+    /// Writes one encoded frame of `block_size` samples per channel.
+    ///
+    /// Returns [`Error::BufferTooSmall`] without writing it when `packets`
+    /// is full.
+    pub fn write_frame<S: SeekableByteSink>(
+        &mut self,
+        sink: &mut S,
+        frame: &[u8],
+        block_size: u16,
+    ) -> Result<(), Error> {
+        let packet = self
+            .packets
+            .get_mut(self.count)
+            .ok_or(Error::BufferTooSmall)?;
+        *packet = CafPacket {
+            size: u32::try_from(frame.len()).map_err(|_| Error::BufferTooSmall)?,
+            frames: u32::from(block_size),
+        };
+        self.count += 1;
+        sink.write_bytes(frame);
+        Ok(())
+    }
+
+    /// Writes the packet table, then fills in the data size, the frames per
+    /// packet and `stream_info` in the cookie.
+    pub fn finish<S: SeekableByteSink>(&mut self, sink: &mut S, stream_info: &StreamInfo) {
+        self.stream_info = Some(*stream_info);
+        let packets = &self.packets[..self.count];
+        let frames_per_packet = self.frames_per_packet();
+        let valid_frames: u64 = packets.iter().map(|packet| u64::from(packet.frames)).sum();
+        let remainder_frames = packets
+            .last()
+            .map_or(0, |last| frames_per_packet.saturating_sub(last.frames));
+        let write_table = |sink: &mut dyn ByteSink| {
+            sink.write_bytes(&(packets.len() as u64).to_be_bytes());
+            sink.write_bytes(&valid_frames.to_be_bytes());
+            // No priming frames.
+            sink.write_bytes(&0u32.to_be_bytes());
+            sink.write_bytes(&remainder_frames.to_be_bytes());
+            for packet in packets {
+                write_variable(sink, packet.size);
+                if frames_per_packet == 0 {
+                    write_variable(sink, packet.frames);
+                }
+            }
+        };
+        let data_end = sink.position();
+        let mut counter = Counter(0);
+        write_table(&mut counter);
+        write_chunk_header(sink, b"pakt", counter.0);
+        write_table(sink);
+        let end = sink.position();
+        sink.seek(self.data_position);
+        write_chunk_header(sink, b"data", data_end - self.data_position - 12);
+        sink.seek(self.desc_position);
+        self.write_desc(sink);
+        sink.seek(self.kuki_position + 12 + FLAC_MARKER.len() as u64 + 4);
+        stream_info.write(sink);
+        sink.seek(end);
+    }
+
+    /// The block size shared by every frame, the last one being allowed to
+    /// be shorter, or 0 when block sizes vary.
+    fn frames_per_packet(&self) -> u32 {
+        let packets = &self.packets[..self.count];
+        let Some((last, packets)) = packets.split_last() else {
+            return 0;
+        };
+        let frames = packets.first().unwrap_or(last).frames;
+        if packets.iter().all(|packet| packet.frames == frames) && last.frames <= frames {
+            frames
+        } else {
+            0
+        }
+    }
+
+    fn write_desc(&self, sink: &mut dyn ByteSink) {
+        let Some(stream_info) = &self.stream_info else {
+            return;
+        };
+        // The Apple Lossless source bit depth flags, which FLAC shares.
+        let format_flags: u32 = match stream_info.bits_per_sample() {
+            16 => 1,
+            20 => 2,
+            24 => 3,
+            32 => 4,
+            _ => 0,
+        };
+        write_chunk_header(sink, b"desc", DESC_LENGTH);
+        sink.write_bytes(&f64::from(stream_info.sample_rate()).to_be_bytes());
+        sink.write_bytes(b"flac");
+        sink.write_bytes(&format_flags.to_be_bytes());
+        // Bytes per packet vary.
+        sink.write_bytes(&0u32.to_be_bytes());
+        sink.write_bytes(&self.frames_per_packet().to_be_bytes());
+        sink.write_bytes(&u32::from(stream_info.channels()).to_be_bytes());
+        sink.write_bytes(&0u32.to_be_bytes());
+    }
+}
+
+#[allow(clippy::cast_possible_wrap)]
+fn write_chunk_header(sink: &mut dyn ByteSink, kind: &[u8; 4], length: u64) {
+    sink.write_bytes(kind);
+    sink.write_bytes(&(length as i64).to_be_bytes());
+}
+
+/// Writes `value` 7 bits per byte, most significant first, the top bit set
+/// on every byte but the last.
+#[allow(clippy::cast_possible_truncation)]
+fn write_variable(sink: &mut dyn ByteSink, value: u32) {
+    let groups = (32 - value.leading_zeros()).div_ceil(7).max(1);
+    for group in (0..groups).rev() {
+        let byte = (value >> (7 * group)) as u8 & 0x7F;
+        sink.write(if group == 0 { byte } else { byte | 0x80 });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CafPacket, CafWriter};
+    use crate::{metadata::stream_info::StreamInfo, BufferByteSink};
+
+    /// Splits `bytes`, past the file header, into chunk types and contents.
+    fn chunks(mut bytes: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+        bytes = &bytes[8..];
+        core::iter::from_fn(move || {
+            if bytes.is_empty() {
+                return None;
+            }
+            let length = u64::from_be_bytes(bytes[4..12].try_into().unwrap()) as usize;
+            let (chunk, rest) = bytes.split_at(12 + length);
+            bytes = rest;
+            Some((&chunk[..4], &chunk[12..]))
+        })
+    }
+
+    #[test]
+    fn chunks_and_packet_table() {
+        let frame = [0x55; 200];
+        let mut stream_info = StreamInfo::new(48_000, 2, 24);
+        let mut packets = [CafPacket::default(); 4];
+        let mut caf = CafWriter::new(&mut packets);
+        let mut sink: BufferByteSink<1024> = BufferByteSink::new();
+        caf.write_header(&mut sink, &stream_info, &[]).unwrap();
+        for (length, block_size) in [(200, 4096), (3, 4096), (10, 1000)] {
+            caf.write_frame(&mut sink, &frame[..length], block_size)
+                .unwrap();
+            stream_info.added_block_with(block_size);
+        }
+        caf.finish(&mut sink, &stream_info);
+
+        let bytes = sink.as_slice();
+        assert_eq!(&bytes[..8], b"caff\0\x01\0\0");
+        let chunks: [_; 4] = core::array::from_fn({
+            let mut chunks = chunks(bytes);
+            move |_| chunks.next().unwrap()
+        });
+        let (kind, desc) = chunks[0];
+        assert_eq!(kind, b"desc");
+        assert_eq!(&desc[8..16], b"flac\0\0\0\x03");
+        assert_eq!(desc[20..28], [0, 0, 16, 0, 0, 0, 0, 2]);
+        let (kind, kuki) = chunks[1];
+        assert_eq!((kind, &kuki[..5]), (&b"kuki"[..], &b"fLaC\x80"[..]));
+        let mut expected: BufferByteSink<34> = BufferByteSink::new();
+        stream_info.write(&mut expected);
+        assert_eq!(&kuki[8..], expected.as_slice());
+        let (kind, data) = chunks[2];
+        assert_eq!((kind, data.len()), (&b"data"[..], 4 + 213));
+        let (kind, pakt) = chunks[3];
+        assert_eq!(kind, b"pakt");
+        assert_eq!(pakt[..8], 3u64.to_be_bytes());
+        assert_eq!(pakt[8..16], 9192u64.to_be_bytes());
+        assert_eq!(pakt[20..24], 3096u32.to_be_bytes());
+        assert_eq!(pakt[24..], [0x81, 0x48, 3, 10]);
+    }
+}
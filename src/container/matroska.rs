@@ -0,0 +1,422 @@
+use super::Counter;
+use crate::{
+    metadata::{stream_info::StreamInfo, MetaDataBlockType, FLAC_MARKER},
+    ByteSink, Error, SeekableByteSink,
+};
+
+const EBML: u32 = 0x1A45_DFA3;
+const SEGMENT: u32 = 0x1853_8067;
+const SEEK_HEAD: u32 = 0x114D_9B74;
+const SEEK: u32 = 0x4DBB;
+const SEEK_ID: u32 = 0x53AB;
+const SEEK_POSITION: u32 = 0x53AC;
+const INFO: u32 = 0x1549_A966;
+const TIMESTAMP_SCALE: u32 = 0x2A_D7B1;
+const DURATION: u32 = 0x4489;
+const MUXING_APP: u32 = 0x4D80;
+const WRITING_APP: u32 = 0x5741;
+const TRACKS: u32 = 0x1654_AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const CODEC_PRIVATE: u32 = 0x63A2;
+const CLUSTER: u32 = 0x1F43_B675;
+const TIMESTAMP: u32 = 0xE7;
+const SIMPLE_BLOCK: u32 = 0xA3;
+const CUES: u32 = 0x1C53_BB6B;
+const VOID: u32 = 0xEC;
+
+/// Longest a cluster gets, in milliseconds, well inside the 16-bit block
+/// timestamps.
+const CLUSTER_DURATION: u64 = 5_000;
+
+const APP_NAME: &[u8] = b"embedded-flac-enc";
+
+/// Where a cluster starts, in milliseconds and in bytes from the start of
+/// the segment data.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CuePoint {
+    pub time: u64,
+    pub cluster_position: u64,
+}
+
+/// Writes an audio-only Matroska file with a single `A_FLAC` track.
+///
+/// Frames go in SimpleBlocks, in clusters of up to five seconds. Every
+/// cluster gets a cue point while `cue_points` has room. Sizes, duration,
+/// STREAMINFO and the cues are filled in by [`finish`](Self::finish), which
+/// is why a [`SeekableByteSink`] is needed.
+pub struct MatroskaWriter<'a> {
+    cue_points: &'a mut [CuePoint],
+    cue_count: usize,
+    sample_rate: u32,
+    samples: u64,
+    segment_start: u64,
+    info_position: u64,
+    tracks_position: u64,
+    cluster: Option<(u64, u64)>,
+}
+
+impl<'a> MatroskaWriter<'a> {
+    pub fn new(cue_points: &'a mut [CuePoint]) -> Self {
+        Self {
+            cue_points,
+            cue_count: 0,
+            sample_rate: 0,
+            samples: 0,
+            segment_start: 0,
+            info_position: 0,
+            tracks_position: 0,
+            cluster: None,
+        }
+    }
+
+    /// Writes the EBML header and everything in the segment ahead of the
+    /// clusters. CodecPrivate holds the `fLaC` marker, STREAMINFO and
+    /// `blocks`.
+    pub fn write_header<S: SeekableByteSink>(
+        &mut self,
+        sink: &mut S,
+        stream_info: &StreamInfo,
+        blocks: &[MetaDataBlockType<'_>],
+    ) -> Result<(), Error> {
+        for block in blocks {
+            block.check_len()?;
+        }
+        self.sample_rate = stream_info.sample_rate();
+        let channels = u64::from(stream_info.channels());
+        let bits_per_sample = u64::from(stream_info.bits_per_sample());
+        master(sink, EBML, |sink| {
+            uint(sink, 0x4286, 1);
+            uint(sink, 0x42F7, 1);
+            uint(sink, 0x42F2, 4);
+            uint(sink, 0x42F3, 8);
+            binary(sink, 0x4282, b"matroska");
+            uint(sink, 0x4287, 4);
+            uint(sink, 0x4285, 2);
+        });
+        write_id(sink, SEGMENT);
+        write_fixed_size(sink, u64::MAX);
+        self.segment_start = sink.position();
+        self.write_seek_head(sink, Some(0));
+        self.info_position = sink.position();
+        self.write_info(sink);
+        self.tracks_position = sink.position();
+        let stream_info = MetaDataBlockType::StreamInfo(*stream_info);
+        master(sink, TRACKS, |sink| {
+            master(sink, TRACK_ENTRY, |mut sink| {
+                // CodecPrivate comes first so `finish` knows where
+                // STREAMINFO is.
+                master(&mut sink, CODEC_PRIVATE, |mut sink| {
+                    sink.write_bytes(&FLAC_MARKER);
+                    let mut blocks = core::iter::once(&stream_info).chain(blocks).peekable();
+                    while let Some(block) = blocks.next() {
+                        let _ = block.write_block(blocks.peek().is_none(), &mut sink);
+                    }
+                });
+                uint(sink, 0xD7, 1);
+                uint(sink, 0x73C5, 1);
+                uint(sink, 0x83, 2);
+                binary(sink, 0x86, b"A_FLAC");
+                master(sink, 0xE1, |sink| {
+                    float(sink, 0xB5, f64::from(self.sample_rate));
+                    uint(sink, 0x9F, channels);
+                    uint(sink, 0x6264, bits_per_sample);
+                });
+            });
+        });
+        Ok(())
+    }
+
+    /// Writes one encoded frame of `block_size` samples per channel as a
+    /// SimpleBlock, starting a new cluster when the current one is full.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn write_frame<S: SeekableByteSink>(
+        &mut self,
+        sink: &mut S,
+        frame: &[u8],
+        block_size: u16,
+    ) {
+        let time = self.time();
+        let cluster_time = match self.cluster {
+            Some((_, cluster_time)) if time - cluster_time < CLUSTER_DURATION => cluster_time,
+            _ => {
+                self.close_cluster(sink);
+                let position = sink.position();
+                if let Some(cue_point) = self.cue_points.get_mut(self.cue_count) {
+                    *cue_point = CuePoint {
+                        time,
+                        cluster_position: position - self.segment_start,
+                    };
+                    self.cue_count += 1;
+                }
+                write_id(sink, CLUSTER);
+                write_fixed_size(sink, u64::MAX);
+                uint(sink, TIMESTAMP, time);
+                self.cluster = Some((position, time));
+                time
+            }
+        };
+        write_id(sink, SIMPLE_BLOCK);
+        write_size(sink, frame.len() as u64 + 4);
+        sink.write(0x81);
+        sink.write_bytes(&((time - cluster_time) as i16).to_be_bytes());
+        sink.write(0x80);
+        sink.write_bytes(frame);
+        self.samples += u64::from(block_size);
+    }
+
+    /// Writes the cues, if any cue point was recorded, and fills in the
+    /// sizes, the duration and `stream_info`, leaving `sink` at the end of
+    /// the file.
+    pub fn finish<S: SeekableByteSink>(&mut self, sink: &mut S, stream_info: &StreamInfo) {
+        self.close_cluster(sink);
+        let cues_position = sink.position() - self.segment_start;
+        let cue_points = &self.cue_points[..self.cue_count];
+        if !cue_points.is_empty() {
+            master(sink, CUES, |sink| {
+                for cue_point in cue_points {
+                    master(sink, 0xBB, |sink| {
+                        uint(sink, 0xB3, cue_point.time);
+                        master(sink, 0xB7, |sink| {
+                            uint(sink, 0xF7, 1);
+                            uint(sink, 0xF1, cue_point.cluster_position);
+                        });
+                    });
+                }
+            });
+        }
+        let end = sink.position();
+        sink.seek(self.segment_start - 8);
+        write_fixed_size(sink, end - self.segment_start);
+        self.write_seek_head(sink, (!cue_points.is_empty()).then_some(cues_position));
+        self.write_info(sink);
+        sink.seek(self.tracks_position + 4 + 8 + 1 + 8 + 2 + 8 + FLAC_MARKER.len() as u64 + 4);
+        stream_info.write(sink);
+        sink.seek(end);
+    }
+
+    fn time(&self) -> u64 {
+        self.samples * 1000 / u64::from(self.sample_rate.max(1))
+    }
+
+    fn close_cluster<S: SeekableByteSink>(&mut self, sink: &mut S) {
+        if let Some((position, _)) = self.cluster.take() {
+            let end = sink.position();
+            sink.seek(position + 4);
+            write_fixed_size(sink, end - position - 4 - 8);
+            sink.seek(end);
+        }
+    }
+
+    /// Every position is written on 8 bytes so the seek head keeps its size
+    /// when `finish` rewrites it. Without cues, their entry is replaced by a
+    /// Void element of the same length.
+    fn write_seek_head<S: SeekableByteSink>(&self, sink: &mut S, cues_position: Option<u64>) {
+        let entries = [
+            Some((INFO, self.info_position.saturating_sub(self.segment_start))),
+            Some((
+                TRACKS,
+                self.tracks_position.saturating_sub(self.segment_start),
+            )),
+            cues_position.map(|position| (CUES, position)),
+        ];
+        master(sink, SEEK_HEAD, |sink| {
+            for (id, position) in entries.iter().flatten() {
+                seek(sink, *id, *position);
+            }
+        });
+        if cues_position.is_none() {
+            let mut counter = Counter(0);
+            seek(&mut counter, CUES, 0);
+            // The Void ID and its fixed size take 9 bytes.
+            write_id(sink, VOID);
+            write_fixed_size(sink, counter.0 - 9);
+            sink.write_repeated(0, (counter.0 - 9) as usize);
+        }
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn write_info<S: SeekableByteSink>(&self, sink: &mut S) {
+        let duration = self.samples as f64 * 1000.0 / f64::from(self.sample_rate.max(1));
+        master(sink, INFO, |sink| {
+            uint(sink, TIMESTAMP_SCALE, 1_000_000);
+            float(sink, DURATION, duration);
+            binary(sink, MUXING_APP, APP_NAME);
+            binary(sink, WRITING_APP, APP_NAME);
+        });
+    }
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn write_id(sink: &mut dyn ByteSink, id: u32) {
+    let skip = (id.leading_zeros() / 8) as usize;
+    sink.write_bytes(&id.to_be_bytes()[skip..]);
+}
+
+/// Writes `size` as the shortest EBML variable-length integer.
+fn write_size(sink: &mut dyn ByteSink, size: u64) {
+    let length = (1..8)
+        .find(|&length| size < (1 << (7 * length)) - 1)
+        .unwrap_or(8);
+    let marked = size | (1 << (7 * length));
+    sink.write_bytes(&marked.to_be_bytes()[8 - length..]);
+}
+
+/// Writes `size` on 8 bytes so it can be overwritten later; `u64::MAX` marks
+/// it unknown.
+fn write_fixed_size(sink: &mut dyn ByteSink, size: u64) {
+    sink.write(0x01);
+    sink.write_bytes(&size.min(0xFF_FFFF_FFFF_FFFF).to_be_bytes()[1..]);
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn uint(sink: &mut dyn ByteSink, id: u32, value: u64) {
+    let length = (8 - value.leading_zeros() as usize / 8).max(1);
+    write_id(sink, id);
+    write_size(sink, length as u64);
+    sink.write_bytes(&value.to_be_bytes()[8 - length..]);
+}
+
+fn float(sink: &mut dyn ByteSink, id: u32, value: f64) {
+    write_id(sink, id);
+    write_size(sink, 8);
+    sink.write_bytes(&value.to_be_bytes());
+}
+
+fn binary(sink: &mut dyn ByteSink, id: u32, bytes: &[u8]) {
+    write_id(sink, id);
+    write_size(sink, bytes.len() as u64);
+    sink.write_bytes(bytes);
+}
+
+/// Writes a Seek entry pointing `id` at `position`.
+fn seek(sink: &mut dyn ByteSink, id: u32, position: u64) {
+    master(sink, SEEK, |sink| {
+        binary(sink, SEEK_ID, &id.to_be_bytes());
+        write_id(sink, SEEK_POSITION);
+        write_size(sink, 8);
+        sink.write_bytes(&position.to_be_bytes());
+    });
+}
+
+/// Writes an element whose content `write` produces, running it once to
+/// measure it. The size always takes 8 bytes.
+fn master(sink: &mut dyn ByteSink, id: u32, write: impl Fn(&mut dyn ByteSink)) {
+    let mut counter = Counter(0);
+    write(&mut counter);
+    write_id(sink, id);
+    write_fixed_size(sink, counter.0);
+    write(sink);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        CuePoint, MatroskaWriter, CLUSTER, CUES, INFO, SEEK, SEEK_HEAD, SEGMENT, TRACKS, VOID,
+    };
+    use crate::{metadata::stream_info::StreamInfo, BufferByteSink};
+
+    /// Reads an element header, returning the ID, the size and the header
+    /// length.
+    fn element(bytes: &[u8]) -> (u32, u64, usize) {
+        let id_length = bytes[0].leading_zeros() as usize + 1;
+        let id = bytes[..id_length]
+            .iter()
+            .fold(0, |id, &byte| (id << 8) | u32::from(byte));
+        let size_length = bytes[id_length].leading_zeros() as usize + 1;
+        let size = bytes[id_length..id_length + size_length]
+            .iter()
+            .fold(0, |size, &byte| (size << 8) | u64::from(byte))
+            & !(1 << (7 * size_length));
+        (id, size, id_length + size_length)
+    }
+
+    #[test]
+    fn segment_layout() {
+        let mut cue_points = [CuePoint::default(); 4];
+        let mut mka = MatroskaWriter::new(&mut cue_points);
+        let mut sink: BufferByteSink<4096> = BufferByteSink::new();
+        let mut stream_info = StreamInfo::new(8_000, 1, 16);
+        mka.write_header(&mut sink, &stream_info, &[]).unwrap();
+        for _ in 0..24 {
+            mka.write_frame(&mut sink, &[0xFF, 0xF8, 0x55], 4000);
+            stream_info.added_block_with(4000);
+        }
+        mka.finish(&mut sink, &stream_info);
+
+        let bytes = sink.as_slice();
+        let (_, size, header) = element(bytes);
+        let segment = &bytes[header + size as usize..];
+        let (id, size, header) = element(segment);
+        assert_eq!((id, header + size as usize), (SEGMENT, segment.len()));
+        let mut data = &segment[header..];
+        let segment_data = data;
+        let mut ids = [0; 8];
+        let mut count = 0;
+        while !data.is_empty() {
+            let (id, size, header) = element(data);
+            ids[count] = id;
+            count += 1;
+            if id == SEEK_HEAD {
+                let cues =
+                    u64::from_be_bytes(data[header + size as usize - 8..][..8].try_into().unwrap());
+                assert_eq!(element(&segment_data[cues as usize..]).0, CUES);
+            }
+            if id == TRACKS {
+                let stream_info_bytes = &data[39..39 + 34];
+                let mut expected: BufferByteSink<34> = BufferByteSink::new();
+                stream_info.write(&mut expected);
+                assert_eq!(stream_info_bytes, expected.as_slice());
+            }
+            data = &data[header + size as usize..];
+        }
+        assert_eq!(
+            ids[..count],
+            [SEEK_HEAD, INFO, TRACKS, CLUSTER, CLUSTER, CLUSTER, CUES]
+        );
+        assert_eq!(
+            cue_points[1],
+            CuePoint {
+                time: 5_000,
+                cluster_position: cue_points[1].cluster_position
+            }
+        );
+    }
+
+    #[test]
+    fn no_cues_without_cue_points() {
+        let mut mka = MatroskaWriter::new(&mut []);
+        let mut sink: BufferByteSink<1024> = BufferByteSink::new();
+        let mut stream_info = StreamInfo::new(8_000, 1, 16);
+        mka.write_header(&mut sink, &stream_info, &[]).unwrap();
+        mka.write_frame(&mut sink, &[0xFF, 0xF8, 0x55], 4000);
+        stream_info.added_block_with(4000);
+        mka.finish(&mut sink, &stream_info);
+
+        let bytes = sink.as_slice();
+        let (_, size, header) = element(bytes);
+        let segment = &bytes[header + size as usize..];
+        let (_, _, header) = element(segment);
+        let mut data = &segment[header..];
+        let mut ids = [0; 8];
+        let mut count = 0;
+        while !data.is_empty() {
+            let (id, size, header) = element(data);
+            ids[count] = id;
+            count += 1;
+            if id == SEEK_HEAD {
+                let mut seeks = &data[header..header + size as usize];
+                let mut seek_count = 0;
+                while !seeks.is_empty() {
+                    let (id, size, header) = element(seeks);
+                    assert_eq!(id, SEEK);
+                    assert_ne!(&seeks[header + 3..header + 7], &CUES.to_be_bytes());
+                    seeks = &seeks[header + size as usize..];
+                    seek_count += 1;
+                }
+                assert_eq!(seek_count, 2);
+            }
+            data = &data[header + size as usize..];
+        }
+        assert_eq!(ids[..count], [SEEK_HEAD, VOID, INFO, TRACKS, CLUSTER]);
+    }
+}
@@ -0,0 +1,27 @@
+//! Writers that wrap encoded frames and metadata in container formats other
+//! than native FLAC.
+
+use crate::ByteSink;
+
+/// Counts what is written to it, to size a box or element before writing it.
+pub(crate) struct Counter(pub(crate) u64);
+
+impl ByteSink for Counter {
+    fn write(&mut self, _: u8) {
+        self.0 += 1;
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        self.0 += bytes.len() as u64;
+    }
+
+    fn write_repeated(&mut self, _: u8, count: usize) {
+        self.0 += count as u64;
+    }
+}
+
+pub mod caf;
+pub mod matroska;
+pub mod mp4;
+pub mod ogg;
+pub mod wave;
@@ -0,0 +1,523 @@
+use super::Counter;
+use crate::{
+    metadata::{stream_info::StreamInfo, MetaDataBlockType},
+    ByteSink, Error, SeekableByteSink,
+};
+
+const TRACK_ID: u32 = 1;
+
+/// Size and duration, in samples per channel, of one frame in `mdat`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Mp4Sample {
+    pub size: u32,
+    pub duration: u32,
+}
+
+/// Writes a progressive MP4 file with a single FLAC track.
+///
+/// `mdat` follows `ftyp` straight away, so frames are written as they come;
+/// their sizes are kept in `samples` until [`finish`](Self::finish) writes
+/// `moov` after them.
+pub struct Mp4Writer<'a> {
+    samples: &'a mut [Mp4Sample],
+    count: usize,
+    mdat_position: u64,
+}
+
+impl<'a> Mp4Writer<'a> {
+    pub fn new(samples: &'a mut [Mp4Sample]) -> Self {
+        Self {
+            samples,
+            count: 0,
+            mdat_position: 0,
+        }
+    }
+
+    /// Writes `ftyp` and opens `mdat`.
+    pub fn write_header<S: SeekableByteSink>(&mut self, sink: &mut S) {
+        write_ftyp(sink);
+        self.mdat_position = sink.position();
+        // A 64-bit size, filled in by `finish`.
+        sink.write_bytes(&1u32.to_be_bytes());
+        sink.write_bytes(b"mdat");
+        sink.write_bytes(&0u64.to_be_bytes());
+    }
+
+    /// Writes one encoded frame of `block_size` samples per channel.
+    ///
+    /// Returns [`Error::BufferTooSmall`] without writing it when `samples`
+    /// is full.
+    pub fn write_frame<S: SeekableByteSink>(
+        &mut self,
+        sink: &mut S,
+        frame: &[u8],
+        block_size: u16,
+    ) -> Result<(), Error> {
+        let sample = self
+            .samples
+            .get_mut(self.count)
+            .ok_or(Error::BufferTooSmall)?;
+        *sample = Mp4Sample {
+            size: u32::try_from(frame.len()).map_err(|_| Error::BufferTooSmall)?,
+            duration: u32::from(block_size),
+        };
+        self.count += 1;
+        sink.write_bytes(frame);
+        Ok(())
+    }
+
+    /// Closes `mdat` and writes `moov`, with `stream_info` and `blocks` in
+    /// the `dfLa` box.
+    pub fn finish<S: SeekableByteSink>(
+        &mut self,
+        sink: &mut S,
+        stream_info: &StreamInfo,
+        blocks: &[MetaDataBlockType<'_>],
+    ) -> Result<(), Error> {
+        for block in blocks {
+            block.check_len()?;
+        }
+        let end = sink.position();
+        sink.seek(self.mdat_position + 8);
+        sink.write_bytes(&(end - self.mdat_position).to_be_bytes());
+        sink.seek(end);
+        let track = Track {
+            stream_info,
+            blocks,
+            samples: Some((&self.samples[..self.count], self.mdat_position + 16)),
+        };
+        write_moov(sink, &track);
+        Ok(())
+    }
+}
+
+/// Writes a fragmented MP4 stream with a single FLAC track, for live
+/// playback and DASH.
+///
+/// `moov` only describes the track; the frames follow in fragments, each a
+/// `moof` and its `mdat`. Nothing is written twice, so any [`ByteSink`]
+/// will do.
+pub struct FragmentedMp4Writer {
+    sequence: u32,
+    decode_time: u64,
+}
+
+impl FragmentedMp4Writer {
+    pub fn new() -> Self {
+        Self {
+            sequence: 0,
+            decode_time: 0,
+        }
+    }
+
+    /// Writes `ftyp` and `moov`.
+    ///
+    /// `moov` is never rewritten, so its STREAMINFO gets `block_size`, the
+    /// encoder's, as both its block sizes.
+    pub fn write_header<BS: ByteSink>(
+        &mut self,
+        sink: &mut BS,
+        stream_info: &StreamInfo,
+        block_size: u16,
+        blocks: &[MetaDataBlockType<'_>],
+    ) -> Result<(), Error> {
+        for block in blocks {
+            block.check_len()?;
+        }
+        write_ftyp(sink);
+        let stream_info = &stream_info.with_block_size(block_size);
+        let track = Track {
+            stream_info,
+            blocks,
+            samples: None,
+        };
+        write_moov(sink, &track);
+        Ok(())
+    }
+
+    /// Writes `frames`, each with its block size, as one fragment.
+    ///
+    /// Returns [`Error::BoxTooLarge`] without writing anything when the
+    /// frames do not fit in one `mdat`.
+    pub fn write_fragment<BS: ByteSink>(
+        &mut self,
+        sink: &mut BS,
+        frames: &[(&[u8], u16)],
+    ) -> Result<(), Error> {
+        let sample_count = u32::try_from(frames.len()).map_err(|_| Error::TooManyBlocks)?;
+        // The `mdat` header included.
+        let mut mdat_length = 8u32;
+        for (frame, _) in frames {
+            mdat_length = u32::try_from(frame.len())
+                .ok()
+                .and_then(|length| mdat_length.checked_add(length))
+                .ok_or(Error::BoxTooLarge)?;
+        }
+        self.sequence += 1;
+        let moof = |sink: &mut dyn ByteSink, data_offset: u32| {
+            write_box(sink, b"moof", |sink| {
+                full_box(sink, b"mfhd", 0, 0, |sink| {
+                    sink.write_bytes(&self.sequence.to_be_bytes());
+                });
+                write_box(sink, b"traf", |sink| {
+                    // Default base is moof.
+                    full_box(sink, b"tfhd", 0, 0x02_0000, |sink| {
+                        sink.write_bytes(&TRACK_ID.to_be_bytes());
+                    });
+                    full_box(sink, b"tfdt", 1, 0, |sink| {
+                        sink.write_bytes(&self.decode_time.to_be_bytes());
+                    });
+                    // Data offset, sample durations and sample sizes.
+                    full_box(sink, b"trun", 0, 0x0301, |sink| {
+                        sink.write_bytes(&sample_count.to_be_bytes());
+                        sink.write_bytes(&data_offset.to_be_bytes());
+                        for (frame, block_size) in frames {
+                            sink.write_bytes(&u32::from(*block_size).to_be_bytes());
+                            #[allow(clippy::cast_possible_truncation)]
+                            sink.write_bytes(&(frame.len() as u32).to_be_bytes());
+                        }
+                    });
+                });
+            });
+        };
+        let mut counter = Counter(0);
+        moof(&mut counter, 0);
+        #[allow(clippy::cast_possible_truncation)]
+        let moof_length = counter.0 as u32;
+        moof(sink, moof_length + 8);
+        sink.write_bytes(&mdat_length.to_be_bytes());
+        sink.write_bytes(b"mdat");
+        for (frame, block_size) in frames {
+            sink.write_bytes(frame);
+            self.decode_time += u64::from(*block_size);
+        }
+        Ok(())
+    }
+}
+
+impl Default for FragmentedMp4Writer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// What `moov` describes. Without samples, the tables are left empty and
+/// `mvex` announces fragments.
+struct Track<'t> {
+    stream_info: &'t StreamInfo,
+    blocks: &'t [MetaDataBlockType<'t>],
+    /// The samples and where their single chunk starts.
+    samples: Option<(&'t [Mp4Sample], u64)>,
+}
+
+fn write_ftyp(sink: &mut dyn ByteSink) {
+    write_box(sink, b"ftyp", |sink| {
+        sink.write_bytes(b"isom");
+        sink.write_bytes(&0u32.to_be_bytes());
+        sink.write_bytes(b"isomiso6mp41");
+    });
+}
+
+fn write_moov(sink: &mut dyn ByteSink, track: &Track) {
+    let stream_info = track.stream_info;
+    let samples = track.samples.map_or(&[][..], |(samples, _)| samples);
+    let duration: u64 = samples
+        .iter()
+        .map(|sample| u64::from(sample.duration))
+        .sum();
+    let timescale = stream_info.sample_rate();
+    write_box(sink, b"moov", |sink| {
+        full_box(sink, b"mvhd", 1, 0, |sink| {
+            sink.write_repeated(0, 16);
+            sink.write_bytes(&timescale.to_be_bytes());
+            sink.write_bytes(&duration.to_be_bytes());
+            sink.write_bytes(&0x0001_0000u32.to_be_bytes());
+            sink.write_bytes(&0x0100u16.to_be_bytes());
+            sink.write_repeated(0, 10);
+            write_matrix(sink);
+            sink.write_repeated(0, 24);
+            sink.write_bytes(&(TRACK_ID + 1).to_be_bytes());
+        });
+        write_box(sink, b"trak", |sink| {
+            // Enabled, in movie and in preview.
+            full_box(sink, b"tkhd", 1, 0x7, |sink| {
+                sink.write_repeated(0, 16);
+                sink.write_bytes(&TRACK_ID.to_be_bytes());
+                sink.write_repeated(0, 4);
+                sink.write_bytes(&duration.to_be_bytes());
+                sink.write_repeated(0, 12);
+                sink.write_bytes(&0x0100u16.to_be_bytes());
+                sink.write_repeated(0, 2);
+                write_matrix(sink);
+                sink.write_repeated(0, 8);
+            });
+            write_box(sink, b"mdia", |sink| {
+                full_box(sink, b"mdhd", 1, 0, |sink| {
+                    sink.write_repeated(0, 16);
+                    sink.write_bytes(&timescale.to_be_bytes());
+                    sink.write_bytes(&duration.to_be_bytes());
+                    // "und", packed on 5 bits per letter.
+                    sink.write_bytes(&0x55C4u16.to_be_bytes());
+                    sink.write_repeated(0, 2);
+                });
+                full_box(sink, b"hdlr", 0, 0, |sink| {
+                    sink.write_repeated(0, 4);
+                    sink.write_bytes(b"soun");
+                    sink.write_repeated(0, 12);
+                    sink.write_bytes(b"SoundHandler\0");
+                });
+                write_box(sink, b"minf", |sink| {
+                    full_box(sink, b"smhd", 0, 0, |sink| sink.write_repeated(0, 4));
+                    write_box(sink, b"dinf", |sink| {
+                        full_box(sink, b"dref", 0, 0, |sink| {
+                            sink.write_bytes(&1u32.to_be_bytes());
+                            // Media data in this file.
+                            full_box(sink, b"url ", 0, 1, |_| {});
+                        });
+                    });
+                    write_box(sink, b"stbl", |sink| write_stbl(sink, track));
+                });
+            });
+        });
+        if track.samples.is_none() {
+            write_box(sink, b"mvex", |sink| {
+                full_box(sink, b"trex", 0, 0, |sink| {
+                    sink.write_bytes(&TRACK_ID.to_be_bytes());
+                    sink.write_bytes(&1u32.to_be_bytes());
+                    sink.write_repeated(0, 12);
+                });
+            });
+        }
+    });
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn write_stbl(sink: &mut dyn ByteSink, track: &Track) {
+    let stream_info = track.stream_info;
+    let (samples, chunk_offset) = track.samples.unwrap_or((&[], 0));
+    full_box(sink, b"stsd", 0, 0, |sink| {
+        sink.write_bytes(&1u32.to_be_bytes());
+        write_box(sink, b"fLaC", |mut sink| {
+            sink.write_repeated(0, 6);
+            sink.write_bytes(&1u16.to_be_bytes());
+            sink.write_repeated(0, 8);
+            sink.write_bytes(&u16::from(stream_info.channels()).to_be_bytes());
+            sink.write_bytes(&u16::from(stream_info.bits_per_sample()).to_be_bytes());
+            sink.write_repeated(0, 4);
+            // 16.16 fixed point, 0 when the rate does not fit.
+            let sample_rate = u16::try_from(stream_info.sample_rate()).unwrap_or(0);
+            sink.write_bytes(&(u32::from(sample_rate) << 16).to_be_bytes());
+            full_box(&mut sink, b"dfLa", 0, 0, |mut sink| {
+                let stream_info = MetaDataBlockType::StreamInfo(*stream_info);
+                let mut blocks = core::iter::once(&stream_info)
+                    .chain(track.blocks)
+                    .peekable();
+                while let Some(block) = blocks.next() {
+                    // Lengths were checked by the caller.
+                    let _ = block.write_block(blocks.peek().is_none(), &mut sink);
+                }
+            });
+        });
+    });
+    let runs = || {
+        let mut samples = samples.iter().peekable();
+        core::iter::from_fn(move || {
+            let duration = samples.next()?.duration;
+            let mut count = 1u32;
+            while samples
+                .next_if(|sample| sample.duration == duration)
+                .is_some()
+            {
+                count += 1;
+            }
+            Some((count, duration))
+        })
+    };
+    full_box(sink, b"stts", 0, 0, |sink| {
+        sink.write_bytes(&(runs().count() as u32).to_be_bytes());
+        for (count, duration) in runs() {
+            sink.write_bytes(&count.to_be_bytes());
+            sink.write_bytes(&duration.to_be_bytes());
+        }
+    });
+    let chunk_count = u32::from(!samples.is_empty());
+    // Every sample goes in a single chunk.
+    full_box(sink, b"stsc", 0, 0, |sink| {
+        sink.write_bytes(&chunk_count.to_be_bytes());
+        if chunk_count != 0 {
+            sink.write_bytes(&1u32.to_be_bytes());
+            sink.write_bytes(&(samples.len() as u32).to_be_bytes());
+            sink.write_bytes(&1u32.to_be_bytes());
+        }
+    });
+    full_box(sink, b"stsz", 0, 0, |sink| {
+        sink.write_repeated(0, 4);
+        sink.write_bytes(&(samples.len() as u32).to_be_bytes());
+        for sample in samples {
+            sink.write_bytes(&sample.size.to_be_bytes());
+        }
+    });
+    // `co64` only when the chunk starts past 4 GiB.
+    match u32::try_from(chunk_offset) {
+        Ok(chunk_offset) => full_box(sink, b"stco", 0, 0, |sink| {
+            sink.write_bytes(&chunk_count.to_be_bytes());
+            if chunk_count != 0 {
+                sink.write_bytes(&chunk_offset.to_be_bytes());
+            }
+        }),
+        Err(_) => full_box(sink, b"co64", 0, 0, |sink| {
+            sink.write_bytes(&chunk_count.to_be_bytes());
+            sink.write_bytes(&chunk_offset.to_be_bytes());
+        }),
+    }
+}
+
+/// The identity matrix, in 16.16 and 2.30 fixed point.
+fn write_matrix(sink: &mut dyn ByteSink) {
+    for value in [0x0001_0000u32, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000] {
+        sink.write_bytes(&value.to_be_bytes());
+    }
+}
+
+/// Writes a box whose content `write` produces, running it once to measure
+/// it.
+#[allow(clippy::cast_possible_truncation)]
+fn write_box(sink: &mut dyn ByteSink, kind: &[u8; 4], write: impl Fn(&mut dyn ByteSink)) {
+    let mut counter = Counter(0);
+    write(&mut counter);
+    sink.write_bytes(&(counter.0 as u32 + 8).to_be_bytes());
+    sink.write_bytes(kind);
+    write(sink);
+}
+
+fn full_box(
+    sink: &mut dyn ByteSink,
+    kind: &[u8; 4],
+    version: u8,
+    flags: u32,
+    write: impl Fn(&mut dyn ByteSink),
+) {
+    write_box(sink, kind, |sink| {
+        sink.write_bytes(&(u32::from(version) << 24 | flags).to_be_bytes());
+        write(sink);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write_stbl, FragmentedMp4Writer, Mp4Sample, Mp4Writer, Track};
+    use crate::{metadata::stream_info::StreamInfo, BufferByteSink};
+
+    /// Finds the first box of `kind` in `bytes`, looking inside `path`
+    /// boxes, and returns its content.
+    fn find<'b>(mut bytes: &'b [u8], path: &[&[u8; 4]]) -> Option<&'b [u8]> {
+        while bytes.len() >= 8 {
+            let size = u32::from_be_bytes(bytes[..4].try_into().unwrap()) as usize;
+            let size = if size == 1 {
+                u64::from_be_bytes(bytes[8..16].try_into().unwrap()) as usize
+            } else {
+                size
+            };
+            let content = &bytes[8..size];
+            if &bytes[4..8] == path[0] {
+                return match path {
+                    [_] => Some(content),
+                    [_, rest @ ..] => find(content, rest),
+                    [] => None,
+                };
+            }
+            bytes = &bytes[size..];
+        }
+        None
+    }
+
+    #[test]
+    fn progressive_and_fragmented() {
+        let frames: [(&[u8], u16); 3] = [
+            (&[0xFF, 0xF8, 1], 4096),
+            (&[0xFF, 0xF8, 2, 2], 4096),
+            (&[0xFF, 0xF8], 100),
+        ];
+        let mut stream_info = StreamInfo::new(44_100, 2, 16);
+        for (_, block_size) in frames {
+            stream_info.added_block_with(block_size);
+        }
+
+        let mut samples = [Mp4Sample::default(); 3];
+        let mut mp4 = Mp4Writer::new(&mut samples);
+        let mut sink: BufferByteSink<2048> = BufferByteSink::new();
+        mp4.write_header(&mut sink);
+        for (frame, block_size) in frames {
+            mp4.write_frame(&mut sink, frame, block_size).unwrap();
+        }
+        assert!(mp4.write_frame(&mut sink, &[0xFF], 1).is_err());
+        mp4.finish(&mut sink, &stream_info, &[]).unwrap();
+        let bytes = sink.as_slice();
+        assert_eq!(&bytes[4..8], b"ftyp");
+        let mdat = find(bytes, &[b"mdat"]).unwrap();
+        assert_eq!(&mdat[8..], [0xFF, 0xF8, 1, 0xFF, 0xF8, 2, 2, 0xFF, 0xF8]);
+        let stbl: &[&[u8; 4]] = &[b"moov", b"trak", b"mdia", b"minf", b"stbl"];
+        let table = |kind| find(bytes, &[stbl, &[kind]].concat()).unwrap();
+        assert_eq!(
+            table(b"stts")[4..],
+            [0, 0, 0, 2, 0, 0, 0, 2, 0, 0, 16, 0, 0, 0, 0, 1, 0, 0, 0, 100]
+        );
+        assert_eq!(table(b"stsz")[12..], [0, 0, 0, 3, 0, 0, 0, 4, 0, 0, 0, 2]);
+        let offset = u32::from_be_bytes(table(b"stco")[8..12].try_into().unwrap()) as usize;
+        assert_eq!(&bytes[offset..offset + 3], [0xFF, 0xF8, 1]);
+        let dfla = &table(b"stsd")[8 + 8 + 28 + 8 + 4..];
+        assert_eq!(dfla[0], 0x80);
+        let mut expected: BufferByteSink<34> = BufferByteSink::new();
+        stream_info.write(&mut expected);
+        assert_eq!(&dfla[4..], expected.as_slice());
+
+        let mut mp4 = FragmentedMp4Writer::new();
+        let mut sink: BufferByteSink<2048> = BufferByteSink::new();
+        let stream_info = StreamInfo::new(44_100, 2, 16);
+        mp4.write_header(&mut sink, &stream_info, 4096, &[])
+            .unwrap();
+        let moov_end = sink.as_slice().len();
+        let dfla =
+            &find(sink.as_slice(), &[stbl, &[b"stsd"]].concat()).unwrap()[8 + 8 + 28 + 8 + 4..];
+        assert_eq!(dfla[4..14], [0x10, 0, 0x10, 0, 0, 0, 0, 0, 0, 0]);
+        mp4.write_fragment(&mut sink, &frames[..2]).unwrap();
+        mp4.write_fragment(&mut sink, &frames[2..]).unwrap();
+        let bytes = sink.as_slice();
+        assert!(find(bytes, &[b"moov", b"mvex", b"trex"]).is_some());
+        let fragment = &bytes[moov_end..];
+        let trun = find(fragment, &[b"moof", b"traf", b"trun"]).unwrap();
+        let data_offset = u32::from_be_bytes(trun[8..12].try_into().unwrap()) as usize;
+        assert_eq!(&fragment[data_offset..data_offset + 3], [0xFF, 0xF8, 1]);
+        let moof_length = u32::from_be_bytes(fragment[..4].try_into().unwrap()) as usize;
+        let mdat_length =
+            u32::from_be_bytes(fragment[moof_length..][..4].try_into().unwrap()) as usize;
+        let tfdt = find(
+            &fragment[moof_length + mdat_length..],
+            &[b"moof", b"traf", b"tfdt"],
+        )
+        .unwrap();
+        assert_eq!(tfdt[4..], 8192u64.to_be_bytes());
+    }
+
+    #[test]
+    fn chunk_offset_past_4_gib() {
+        let stream_info = StreamInfo::new(44_100, 2, 16);
+        let samples = [Mp4Sample {
+            size: 10,
+            duration: 4096,
+        }];
+        let offset = (1 << 32) + 16;
+        let track = Track {
+            stream_info: &stream_info,
+            blocks: &[],
+            samples: Some((&samples, offset)),
+        };
+        let mut sink: BufferByteSink<512> = BufferByteSink::new();
+        write_stbl(&mut sink, &track);
+        let bytes = sink.as_slice();
+        assert!(find(bytes, &[b"stco"]).is_none());
+        let co64 = find(bytes, &[b"co64"]).unwrap();
+        assert_eq!(co64[4..8], 1u32.to_be_bytes());
+        assert_eq!(co64[8..], offset.to_be_bytes());
+    }
+}
@@ -0,0 +1,286 @@
+use crate::{
+    metadata::{stream_info::StreamInfo, MetaDataBlockType, FLAC_MARKER},
+    utils::crc32_update,
+    ByteSink, Error,
+};
+
+const CRC_POLYNOMIAL: u32 = 0x04C1_1DB7;
+const MAX_SEGMENTS: usize = 255;
+const MAX_PAGE_DATA: usize = MAX_SEGMENTS * 255;
+
+const CONTINUED: u8 = 0x01;
+const BEGINNING_OF_STREAM: u8 = 0x02;
+const END_OF_STREAM: u8 = 0x04;
+
+/// Wraps a FLAC stream in Ogg pages following the Ogg FLAC mapping.
+///
+/// [`write_headers`](Self::write_headers) goes first, then every frame
+/// through [`write_frame`](Self::write_frame), one frame per page, and
+/// [`finish`](Self::finish) last.
+pub struct OggWriter {
+    serial: u32,
+    sequence: u32,
+    granule: u64,
+}
+
+impl OggWriter {
+    /// `serial` tells this logical stream apart from others multiplexed
+    /// with it.
+    pub fn new(serial: u32) -> Self {
+        Self {
+            serial,
+            sequence: 0,
+            granule: 0,
+        }
+    }
+
+    /// Writes the identification packet holding STREAMINFO, then one packet
+    /// per block of `blocks`, which must start with the VORBIS_COMMENT.
+    ///
+    /// STREAMINFO is never rewritten, so it gets `block_size`, the
+    /// encoder's, as both its block sizes.
+    pub fn write_headers<BS: ByteSink>(
+        &mut self,
+        sink: &mut BS,
+        stream_info: &StreamInfo,
+        block_size: u16,
+        blocks: &[MetaDataBlockType<'_>],
+    ) -> Result<(), Error> {
+        if !matches!(blocks.first(), Some(MetaDataBlockType::VorbisComent(_))) {
+            return Err(Error::MissingVorbisComment);
+        }
+        let header_count = u16::try_from(blocks.len()).map_err(|_| Error::TooManyBlocks)?;
+        for block in blocks {
+            block.check_len()?;
+        }
+        // Lengths are checked above, so writing the blocks cannot fail.
+        let stream_info = MetaDataBlockType::StreamInfo(stream_info.with_block_size(block_size));
+        let length = 1 + 4 + 2 + 2 + FLAC_MARKER.len() + 4 + StreamInfo::LENGTH;
+        self.write_packet(sink, length, BEGINNING_OF_STREAM, |mut sink| {
+            sink.write(0x7F);
+            sink.write_bytes(b"FLAC");
+            sink.write_bytes(&[1, 0]);
+            sink.write_bytes(&header_count.to_be_bytes());
+            sink.write_bytes(&FLAC_MARKER);
+            let _ = stream_info.write_block(blocks.is_empty(), &mut sink);
+        });
+        for (i, block) in blocks.iter().enumerate() {
+            let is_last = i + 1 == blocks.len();
+            self.write_packet(sink, 4 + block.len(), 0, |mut sink| {
+                let _ = block.write_block(is_last, &mut sink);
+            });
+        }
+        Ok(())
+    }
+
+    /// Writes one encoded frame of `block_size` samples per channel as its
+    /// own packet.
+    pub fn write_frame<BS: ByteSink>(&mut self, sink: &mut BS, frame: &[u8], block_size: u16) {
+        self.granule += u64::from(block_size);
+        self.write_packet(sink, frame.len(), 0, |sink| sink.write_bytes(frame));
+    }
+
+    /// Ends the logical stream with an empty last page.
+    pub fn finish<BS: ByteSink>(&mut self, sink: &mut BS) {
+        self.write_page(sink, END_OF_STREAM, self.granule, &[], |_| {});
+    }
+
+    /// Splits a packet of `length` bytes, produced by `write`, into pages.
+    fn write_packet<BS: ByteSink>(
+        &mut self,
+        sink: &mut BS,
+        length: usize,
+        flags: u8,
+        write: impl Fn(&mut dyn ByteSink),
+    ) {
+        let mut start = 0;
+        loop {
+            let remaining = length - start;
+            let ends = remaining < MAX_PAGE_DATA;
+            let end = if ends { length } else { start + MAX_PAGE_DATA };
+            let mut lacing = [255; MAX_SEGMENTS];
+            let segments = if ends {
+                lacing[remaining / 255] = (remaining % 255) as u8;
+                remaining / 255 + 1
+            } else {
+                MAX_SEGMENTS
+            };
+            let flags = if start == 0 { flags } else { CONTINUED };
+            let granule = if ends { self.granule } else { u64::MAX };
+            self.write_page(sink, flags, granule, &lacing[..segments], |sink| {
+                write(&mut Window {
+                    sink,
+                    position: 0,
+                    start,
+                    end,
+                });
+            });
+            if ends {
+                return;
+            }
+            start = end;
+        }
+    }
+
+    /// Writes a page whose data `write` produces, running it once to
+    /// checksum the page and once to output it.
+    fn write_page<BS: ByteSink>(
+        &mut self,
+        sink: &mut BS,
+        flags: u8,
+        granule: u64,
+        lacing: &[u8],
+        write: impl Fn(&mut dyn ByteSink),
+    ) {
+        let mut header = [0; 27];
+        header[..4].copy_from_slice(b"OggS");
+        header[5] = flags;
+        header[6..14].copy_from_slice(&granule.to_le_bytes());
+        header[14..18].copy_from_slice(&self.serial.to_le_bytes());
+        header[18..22].copy_from_slice(&self.sequence.to_le_bytes());
+        #[allow(clippy::cast_possible_truncation)]
+        {
+            header[26] = lacing.len() as u8;
+        }
+        let mut crc = CrcSink { crc: 0 };
+        crc.write_bytes(&header);
+        crc.write_bytes(lacing);
+        write(&mut crc);
+        header[22..26].copy_from_slice(&crc.crc.to_le_bytes());
+        sink.write_bytes(&header);
+        sink.write_bytes(lacing);
+        write(sink);
+        self.sequence += 1;
+    }
+}
+
+struct CrcSink {
+    crc: u32,
+}
+
+impl ByteSink for CrcSink {
+    fn write(&mut self, next_byte: u8) {
+        self.crc = crc32_update(self.crc, next_byte, CRC_POLYNOMIAL);
+    }
+}
+
+/// Passes on only the bytes from `start` to `end` of what is written to it.
+struct Window<'a> {
+    sink: &'a mut dyn ByteSink,
+    position: usize,
+    start: usize,
+    end: usize,
+}
+
+impl ByteSink for Window<'_> {
+    fn write(&mut self, next_byte: u8) {
+        if (self.start..self.end).contains(&self.position) {
+            self.sink.write(next_byte);
+        }
+        self.position += 1;
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        let start = self.start.saturating_sub(self.position).min(bytes.len());
+        let end = self.end.saturating_sub(self.position).min(bytes.len());
+        if start < end {
+            self.sink.write_bytes(&bytes[start..end]);
+        }
+        self.position += bytes.len();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{OggWriter, CRC_POLYNOMIAL};
+    use crate::{
+        metadata::{
+            padding::Padding, stream_info::StreamInfo, vorbis_comment::VorbisCommentBuffer,
+            MetaDataBlockType,
+        },
+        utils::crc32_update,
+        BufferByteSink, Error,
+    };
+
+    /// Splits `bytes` into pages, checking each checksum, and returns their
+    /// header type, granule and data length.
+    fn pages(mut bytes: &[u8]) -> impl Iterator<Item = (u8, u64, usize)> + '_ {
+        core::iter::from_fn(move || {
+            if bytes.is_empty() {
+                return None;
+            }
+            assert_eq!(&bytes[..4], b"OggS");
+            let segments = usize::from(bytes[26]);
+            let data: usize = bytes[27..27 + segments]
+                .iter()
+                .map(|&s| usize::from(s))
+                .sum();
+            let (page, rest) = bytes.split_at(27 + segments + data);
+            let crc = page
+                .iter()
+                .enumerate()
+                .map(|(i, &byte)| if (22..26).contains(&i) { 0 } else { byte })
+                .fold(0, |crc, byte| crc32_update(crc, byte, CRC_POLYNOMIAL));
+            assert_eq!(crc.to_le_bytes(), page[22..26]);
+            bytes = rest;
+            let granule = u64::from_le_bytes(page[6..14].try_into().unwrap());
+            Some((page[5], granule, data))
+        })
+    }
+
+    #[test]
+    fn pages_and_granules() {
+        let mut sink: BufferByteSink<{ 80 * 1024 }> = BufferByteSink::new();
+        let mut ogg = OggWriter::new(7);
+        let stream_info = StreamInfo::new(44_100, 2, 16);
+        let padding = Padding::new(10);
+        assert_eq!(
+            ogg.write_headers(
+                &mut sink,
+                &stream_info,
+                4096,
+                &[MetaDataBlockType::Padding(padding)]
+            ),
+            Err(Error::MissingVorbisComment)
+        );
+        let comment: VorbisCommentBuffer<16> = VorbisCommentBuffer::new("dev").unwrap();
+        let blocks = [
+            MetaDataBlockType::VorbisComent(comment.block()),
+            MetaDataBlockType::Padding(padding),
+        ];
+        ogg.write_headers(&mut sink, &stream_info, 4096, &blocks)
+            .unwrap();
+        ogg.write_frame(&mut sink, &[0xFF, 0xF8, 0, 0], 4096);
+        let big_frame = [0x55; 255 * 255 + 10];
+        ogg.write_frame(&mut sink, &big_frame, 1000);
+        ogg.finish(&mut sink);
+
+        let bytes = sink.as_slice();
+        assert_eq!(&bytes[28..28 + 9], b"\x7fFLAC\x01\x00\x00\x02");
+        // Block sizes of 4096 and unknown frame sizes.
+        let mut expected: BufferByteSink<34> = BufferByteSink::new();
+        stream_info.with_block_size(4096).write(&mut expected);
+        let stream_info_bytes = &bytes[28 + 9 + 4 + 4..][..34];
+        assert_eq!(stream_info_bytes, expected.as_slice());
+        assert_eq!(
+            stream_info_bytes[..10],
+            [0x10, 0, 0x10, 0, 0, 0, 0, 0, 0, 0]
+        );
+        let pages: [_; 7] = core::array::from_fn({
+            let mut pages = pages(bytes);
+            move |_| pages.next().unwrap()
+        });
+        assert_eq!(
+            pages,
+            [
+                (0x02, 0, 51),
+                (0x00, 0, 15),
+                (0x00, 0, 14),
+                (0x00, 4096, 4),
+                (0x00, u64::MAX, 255 * 255),
+                (0x01, 5096, 10),
+                (0x04, 5096, 0),
+            ]
+        );
+    }
+}
@@ -0,0 +1,122 @@
+use crate::{metadata::stream_info::StreamInfo, SeekableByteSink};
+
+/// The format tag some Windows tools use for a native FLAC stream in a
+/// RIFF/WAVE file.
+pub const WAVE_FORMAT_FLAC: u16 = 0xF1AC;
+
+const FMT_LENGTH: u32 = 18 + StreamInfo::LENGTH as u32;
+
+/// Wraps the native FLAC stream written by the encoder in a RIFF/WAVE file
+/// with format tag [`WAVE_FORMAT_FLAC`].
+///
+/// The fmt chunk carries the STREAMINFO payload as its extension data.
+/// [`write_header`](Self::write_header) goes before the encoder writes
+/// anything, and [`finish`](Self::finish) after the encoder is finished, to
+/// fill in the RIFF sizes and STREAMINFO.
+pub struct WaveWriter {
+    riff_position: u64,
+    data_position: u64,
+}
+
+impl WaveWriter {
+    pub fn new() -> Self {
+        Self {
+            riff_position: 0,
+            data_position: 0,
+        }
+    }
+
+    /// Writes the RIFF header, the fmt chunk and opens the data chunk.
+    pub fn write_header<S: SeekableByteSink>(&mut self, sink: &mut S, stream_info: &StreamInfo) {
+        self.riff_position = sink.position();
+        sink.write_bytes(b"RIFF");
+        // Filled in by `finish`.
+        sink.write_bytes(&0u32.to_le_bytes());
+        sink.write_bytes(b"WAVE");
+        sink.write_bytes(b"fmt ");
+        sink.write_bytes(&FMT_LENGTH.to_le_bytes());
+        let channels = u16::from(stream_info.channels());
+        let bits_per_sample = u16::from(stream_info.bits_per_sample());
+        let bytes_per_second =
+            stream_info.sample_rate() * u32::from(channels) * u32::from(bits_per_sample) / 8;
+        sink.write_bytes(&WAVE_FORMAT_FLAC.to_le_bytes());
+        sink.write_bytes(&channels.to_le_bytes());
+        sink.write_bytes(&stream_info.sample_rate().to_le_bytes());
+        // Uncompressed rate, frames varying in size.
+        sink.write_bytes(&bytes_per_second.to_le_bytes());
+        sink.write_bytes(&1u16.to_le_bytes());
+        sink.write_bytes(&bits_per_sample.to_le_bytes());
+        #[allow(clippy::cast_possible_truncation)]
+        sink.write_bytes(&(StreamInfo::LENGTH as u16).to_le_bytes());
+        stream_info.write(sink);
+        self.data_position = sink.position();
+        sink.write_bytes(b"data");
+        sink.write_bytes(&0u32.to_le_bytes());
+    }
+
+    /// Closes the data chunk, padding it to an even length, and fills in
+    /// the chunk sizes and `stream_info`. Sizes past 4 GiB are left at
+    /// their maximum.
+    pub fn finish<S: SeekableByteSink>(&mut self, sink: &mut S, stream_info: &StreamInfo) {
+        let data_length = sink.position() - self.data_position - 8;
+        if data_length % 2 == 1 {
+            sink.write(0);
+        }
+        let end = sink.position();
+        let size = |length: u64| u32::try_from(length).unwrap_or(u32::MAX).to_le_bytes();
+        sink.seek(self.riff_position + 4);
+        sink.write_bytes(&size(end - self.riff_position - 8));
+        sink.seek(self.data_position - StreamInfo::LENGTH as u64);
+        stream_info.write(sink);
+        sink.seek(self.data_position + 4);
+        sink.write_bytes(&size(data_length));
+        sink.seek(end);
+    }
+}
+
+impl Default for WaveWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WaveWriter;
+    use crate::{
+        encoder::{Encoder, EncoderWorkspace},
+        metadata::FLAC_MARKER,
+        BufferByteSink,
+    };
+
+    #[test]
+    fn wraps_encoder_output() {
+        let ramp: [i32; 32] = core::array::from_fn(|i| (i * 3) as i32);
+        let mut workspace: EncoderWorkspace<32> = EncoderWorkspace::new();
+        let mut encoder: Encoder<1, 32> = Encoder::new(&mut workspace, 8_000, 16).unwrap();
+        let mut sink: BufferByteSink<1024> = BufferByteSink::new();
+        let mut wave = WaveWriter::new();
+        wave.write_header(&mut sink, encoder.stream_info());
+        encoder.write_metadata(&mut sink, &[]).unwrap();
+        encoder.encode(&mut sink, [&ramp]).unwrap();
+        encoder.encode(&mut sink, [&ramp[..7]]).unwrap();
+        encoder.finish(&mut sink).unwrap();
+        wave.finish(&mut sink, encoder.stream_info());
+
+        let bytes = sink.as_slice();
+        let le_u32 = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap()) as usize;
+        assert_eq!(&bytes[..4], b"RIFF");
+        assert_eq!(le_u32(4), bytes.len() - 8);
+        assert_eq!(&bytes[8..16], b"WAVEfmt ");
+        assert_eq!(le_u32(16), 52);
+        assert_eq!(bytes[20..22], [0xAC, 0xF1]);
+        let data = 20 + 52;
+        assert_eq!(&bytes[data..data + 4], b"data");
+        let stream = &bytes[data + 8..data + 8 + le_u32(data + 4)];
+        assert_eq!(&stream[..4], &FLAC_MARKER);
+        // The encoder patched its own STREAMINFO, and the fmt copy matches.
+        assert_eq!(stream[8..8 + 34], bytes[data - 34..data]);
+        assert_eq!(stream[8 + 17], 32 + 7);
+        assert_eq!(bytes.len() % 2, 0);
+    }
+}
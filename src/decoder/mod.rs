@@ -0,0 +1,376 @@
+//! Decoding of single frames, to check what the encoder writes.
+
+use crate::{
+    frames::{
+        header::{ChannelBits, FrameHeader},
+        sub_frame::FIXED_COEFFICIENTS,
+    },
+    metadata::stream_info::StreamInfo,
+    utils::crc16_update,
+    BitSource, Error, SliceBitSource,
+};
+
+/// What a frame header says about its frame, and the frame's length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameInfo {
+    /// Frame number in a fixed-blocksize stream, first sample number
+    /// otherwise.
+    pub number: u64,
+    pub is_variable_block_size: bool,
+    pub block_size: u32,
+    pub sample_rate: u32,
+    pub channels: usize,
+    pub bit_depth: u8,
+    /// Bytes taken by the frame, CRC-16 included.
+    pub length: usize,
+}
+
+/// Decodes frames into per-channel blocks of `i32` samples.
+///
+/// Sample rate and bit depth come from STREAMINFO when a frame header leaves
+/// them out.
+pub struct FrameDecoder {
+    sample_rate: u32,
+    bit_depth: u8,
+}
+
+impl FrameDecoder {
+    pub fn new(stream_info: &StreamInfo) -> Self {
+        Self {
+            sample_rate: stream_info.sample_rate(),
+            bit_depth: stream_info.bits_per_sample(),
+        }
+    }
+
+    /// Decodes the frame at the start of `bytes` into the start of
+    /// `channels`, one slice per channel.
+    ///
+    /// Both checksums are verified. Returns [`Error::BufferTooSmall`] when
+    /// `channels` has too few slices or they are too short for the block.
+    pub fn decode(&self, bytes: &[u8], channels: &mut [&mut [i32]]) -> Result<FrameInfo, Error> {
+        let (mut info, channel_bits) = self.read_header(bytes)?;
+        let mut reader = SliceBitSource::new(bytes.get(info.length..).ok_or(Error::UnexpectedEnd)?);
+        let block_size = usize::try_from(info.block_size).map_err(|_| Error::BufferTooSmall)?;
+        let channels = channels
+            .get_mut(..info.channels)
+            .ok_or(Error::BufferTooSmall)?;
+        let side_channel = channel_bits.side_channel();
+        for (channel, samples) in channels.iter_mut().enumerate() {
+            let samples = samples.get_mut(..block_size).ok_or(Error::BufferTooSmall)?;
+            let bit_depth = info.bit_depth + u8::from(side_channel == Some(channel));
+            read_sub_frame(&mut reader, bit_depth, samples)?;
+        }
+        reader.align();
+        let length = info.length + reader.byte_position();
+        let crc = reader.read_bits(16).ok_or(Error::UnexpectedEnd)?;
+        let expected = bytes[..length].iter().fold(0, |crc, &byte| {
+            crc16_update(crc, byte, 0b1000_0000_0000_0101)
+        });
+        if crc != u32::from(expected) {
+            return Err(Error::CrcMismatch);
+        }
+        if let [first, second, ..] = channels {
+            undo_decorrelation(
+                channel_bits,
+                &mut first[..block_size],
+                &mut second[..block_size],
+            );
+        }
+        info.length = length + 2;
+        Ok(info)
+    }
+
+    /// Reads and checks the frame header, returning it with its channel
+    /// layout.
+    fn read_header(&self, bytes: &[u8]) -> Result<(FrameInfo, ChannelBits), Error> {
+        let header = FrameHeader::parse(bytes)?;
+        let info = FrameInfo {
+            number: header.number(),
+            is_variable_block_size: header.is_variable_block_size(),
+            block_size: header.block_size(),
+            sample_rate: header.sample_rate().unwrap_or(self.sample_rate),
+            channels: header.channel_bits().channels(),
+            bit_depth: header.bit_depth().unwrap_or(self.bit_depth),
+            length: header.length(),
+        };
+        Ok((info, header.channel_bits()))
+    }
+}
+
+fn read_sub_frame(
+    reader: &mut SliceBitSource,
+    bit_depth: u8,
+    samples: &mut [i32],
+) -> Result<(), Error> {
+    let mut read = |bits| reader.read_bits(bits).ok_or(Error::UnexpectedEnd);
+    if read(1)? != 0 {
+        return Err(Error::InvalidFrame);
+    }
+    let sub_frame_type = read(6)?;
+    let wasted_bits = if read(1)? == 1 {
+        reader.read_unary().ok_or(Error::UnexpectedEnd)? + 1
+    } else {
+        0
+    };
+    let bit_depth = u32::from(bit_depth)
+        .checked_sub(wasted_bits)
+        .ok_or(Error::InvalidFrame)?;
+    if bit_depth > 32 {
+        return Err(Error::InvalidBitDepth);
+    }
+    #[allow(clippy::cast_possible_truncation)]
+    let bit_depth = bit_depth as u8;
+    let mut read_sample = || reader.read_signed(bit_depth).ok_or(Error::UnexpectedEnd);
+    match sub_frame_type {
+        0b00_0000 => {
+            let sample = read_sample()?;
+            samples.fill(sample);
+        }
+        0b00_0001 => {
+            for sample in samples.iter_mut() {
+                *sample = read_sample()?;
+            }
+        }
+        0b00_1000..=0b00_1100 => {
+            let order = (sub_frame_type & 0b111) as usize;
+            if order > samples.len() {
+                return Err(Error::InvalidFrame);
+            }
+            for sample in &mut samples[..order] {
+                *sample = read_sample()?;
+            }
+            read_residuals(reader, order, samples)?;
+            predict(samples, FIXED_COEFFICIENTS[order], 0)?;
+        }
+        0b10_0000..=0b11_1111 => {
+            let order = (sub_frame_type & 0b1_1111) as usize + 1;
+            if order > samples.len() {
+                return Err(Error::InvalidFrame);
+            }
+            for sample in &mut samples[..order] {
+                *sample = read_sample()?;
+            }
+            let mut read = |bits| reader.read_bits(bits).ok_or(Error::UnexpectedEnd);
+            let precision = read(4)? + 1;
+            // Signed, but a negative shift is not allowed.
+            let shift = read(5)?;
+            if precision == 16 || shift >= 0b1_0000 {
+                return Err(Error::InvalidFrame);
+            }
+            let mut coefficients = [0; 32];
+            for coefficient in &mut coefficients[..order] {
+                #[allow(clippy::cast_possible_truncation)]
+                let value = reader.read_signed(precision as u8);
+                *coefficient = i64::from(value.ok_or(Error::UnexpectedEnd)?);
+            }
+            read_residuals(reader, order, samples)?;
+            predict(samples, &coefficients[..order], shift)?;
+        }
+        _ => return Err(Error::InvalidFrame),
+    }
+    if wasted_bits != 0 {
+        for sample in samples {
+            *sample = sample.checked_shl(wasted_bits).ok_or(Error::InvalidFrame)?;
+        }
+    }
+    Ok(())
+}
+
+/// Reads the partitioned Rice residuals into `samples[order..]`.
+fn read_residuals(
+    reader: &mut SliceBitSource,
+    order: usize,
+    samples: &mut [i32],
+) -> Result<(), Error> {
+    let mut read = |bits| reader.read_bits(bits).ok_or(Error::UnexpectedEnd);
+    let parameter_bits = match read(2)? {
+        0b00 => 4,
+        0b01 => 5,
+        _ => return Err(Error::InvalidFrame),
+    };
+    let partition_order = read(4)?;
+    let partitions = 1 << partition_order;
+    let partition_size = samples.len() >> partition_order;
+    if !samples.len().is_multiple_of(partitions) || partition_size < order {
+        return Err(Error::InvalidFrame);
+    }
+    let escape = (1 << parameter_bits) - 1;
+    for partition in 0..partitions {
+        let start = if partition == 0 {
+            order
+        } else {
+            partition * partition_size
+        };
+        let residuals = &mut samples[start..(partition + 1) * partition_size];
+        let parameter = reader
+            .read_bits(parameter_bits)
+            .ok_or(Error::UnexpectedEnd)?;
+        #[allow(clippy::cast_possible_truncation)]
+        if parameter == escape {
+            let bits = reader.read_bits(5).ok_or(Error::UnexpectedEnd)? as u8;
+            for residual in residuals {
+                *residual = reader.read_signed(bits).ok_or(Error::UnexpectedEnd)?;
+            }
+        } else {
+            for residual in residuals {
+                *residual = reader
+                    .read_rice(parameter as u8)
+                    .ok_or(Error::UnexpectedEnd)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Adds to each residual from `samples[coefficients.len()..]` its
+/// prediction, `coefficients` applying to the preceding samples, newest
+/// first.
+fn predict(samples: &mut [i32], coefficients: &[i64], shift: u32) -> Result<(), Error> {
+    for i in coefficients.len()..samples.len() {
+        let prediction = coefficients
+            .iter()
+            .enumerate()
+            .map(|(j, &coefficient)| coefficient * i64::from(samples[i - 1 - j]))
+            .sum::<i64>()
+            >> shift;
+        samples[i] =
+            i32::try_from(i64::from(samples[i]) + prediction).map_err(|_| Error::InvalidFrame)?;
+    }
+    Ok(())
+}
+
+/// Turns left/side, side/right or mid/side back into left and right.
+fn undo_decorrelation(channel_bits: ChannelBits, first: &mut [i32], second: &mut [i32]) {
+    for (first, second) in first.iter_mut().zip(second) {
+        let (left, right) = match channel_bits {
+            ChannelBits::LeftRightStoredAsLeftMinusSideAndStereo => {
+                (*first, first.wrapping_sub(*second))
+            }
+            ChannelBits::LeftRightStoredAsSideMinusightAndStereo => {
+                (first.wrapping_add(*second), *second)
+            }
+            ChannelBits::LeftRightStoredAsMidMinusSideAndStereo => {
+                let mid = (i64::from(*first) << 1) | i64::from(*second & 1);
+                let side = i64::from(*second);
+                #[allow(clippy::cast_possible_truncation)]
+                (((mid + side) >> 1) as i32, ((mid - side) >> 1) as i32)
+            }
+            _ => return,
+        };
+        *first = left;
+        *second = right;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FrameDecoder;
+    use crate::{
+        encoder::{Encoder, EncoderWorkspace},
+        frames::{header::ChannelBits, sub_frame::SubFrameType, Frame},
+        metadata::stream_info::StreamInfo,
+        utils::crc8_remainder,
+        BufferByteSink, Error,
+    };
+
+    /// A mono frame header for `block_size_code`, rate and depth from
+    /// STREAMINFO, followed by `extra` and the CRC-8.
+    fn header(block_size_code: u8, extra: &[u8]) -> [u8; 8] {
+        let mut header = [0; 8];
+        header[..5].copy_from_slice(&[0xFF, 0xF8, block_size_code << 4, 0, 0]);
+        header[5..5 + extra.len()].copy_from_slice(extra);
+        header[5 + extra.len()] = crc8_remainder(&header[..5 + extra.len()], 7, 0);
+        header
+    }
+
+    #[test]
+    fn decodes_encoder_output() {
+        let mut noise = 1_u32;
+        let left: [i32; 576] = core::array::from_fn(|i| {
+            noise = noise.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let triangle = ((i % 200) as i32 - 100).abs() * 10_000;
+            triangle + (noise >> 24) as i32 - 128
+        });
+        let right: [i32; 576] = core::array::from_fn(|i| (left[i] >> 4) << 4);
+        let silence = [-7; 576];
+        let mut workspace: EncoderWorkspace<576> = EncoderWorkspace::new();
+        let mut encoder: Encoder<3, 576> = Encoder::new(&mut workspace, 96_000, 24).unwrap();
+        let decoder = FrameDecoder::new(encoder.stream_info());
+        let mut out = [0; 4096];
+        let mut decoded = [[0; 576]; 3];
+        for frame_number in 0..2 {
+            let block_size = 576 - frame_number * 100;
+            let input = [
+                &left[..block_size],
+                &right[..block_size],
+                &silence[..block_size],
+            ];
+            let length = encoder.encode_frame_into(input, &mut out).unwrap();
+            let [a, b, c] = &mut decoded;
+            let info = decoder.decode(&out[..length], &mut [a, b, c]).unwrap();
+            assert_eq!(info.number, frame_number as u64);
+            assert_eq!((info.block_size, info.length), (block_size as u32, length));
+            for (input, decoded) in input.iter().zip(&decoded) {
+                assert_eq!(*input, &decoded[..block_size]);
+            }
+            out[length / 2] ^= 0x10;
+            let [a, b, c] = &mut decoded;
+            assert_eq!(
+                decoder.decode(&out[..length], &mut [a, b, c]),
+                Err(Error::CrcMismatch)
+            );
+        }
+
+        let left: [i32; 64] = core::array::from_fn(|i| (i * i) as i32 - 2000);
+        let right: [i32; 64] = core::array::from_fn(|i| 3 * i as i32 - 1000);
+        let mid: [i32; 64] = core::array::from_fn(|i| (left[i] + right[i]) >> 1);
+        let side: [i32; 64] = core::array::from_fn(|i| left[i] - right[i]);
+        let frame: Frame<2, 64> = Frame::new(
+            44_100,
+            ChannelBits::LeftRightStoredAsMidMinusSideAndStereo,
+            16,
+            0,
+            SubFrameType::FixedPredictorOrder2,
+            0,
+            [&mid, &side],
+        )
+        .unwrap();
+        let mut sink: BufferByteSink<512> = BufferByteSink::new();
+        let length = frame.write(&mut sink);
+        let decoder = FrameDecoder::new(&StreamInfo::new(44_100, 2, 16));
+        let [mut a, mut b] = [[0; 64]; 2];
+        let mut short = [0; 63];
+        assert_eq!(
+            decoder.decode(sink.as_slice(), &mut [&mut a, &mut short]),
+            Err(Error::BufferTooSmall)
+        );
+        let info = decoder
+            .decode(sink.as_slice(), &mut [&mut a, &mut b])
+            .unwrap();
+        assert_eq!((info.channels, info.length), (2, length));
+        assert_eq!((a, b), (left, right));
+    }
+
+    #[test]
+    fn largest_block_and_negative_shift() {
+        let decoder = FrameDecoder::new(&StreamInfo::new(44_100, 1, 16));
+        let (info, _) = decoder.read_header(&header(0b0111, &[0xFF, 0xFF])).unwrap();
+        assert_eq!((info.block_size, info.length), (65_536, 8));
+
+        // 192 samples, LPC order 1 with a warm-up sample of 0, a precision
+        // of 1 and a shift of -16, then 15.
+        let mut bytes = [0; 16];
+        bytes[..6].copy_from_slice(&header(0b0001, &[])[..6]);
+        bytes[6..10].copy_from_slice(&[0b0100_0000, 0, 0, 0b0000_1000]);
+        let mut samples = [0; 192];
+        assert_eq!(
+            decoder.decode(&bytes, &mut [&mut samples]),
+            Err(Error::InvalidFrame)
+        );
+        bytes[9..11].copy_from_slice(&[0b0000_0111, 0b1000_0000]);
+        assert_eq!(
+            decoder.decode(&bytes, &mut [&mut samples]),
+            Err(Error::UnexpectedEnd)
+        );
+    }
+}
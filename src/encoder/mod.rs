@@ -0,0 +1,486 @@
+use crate::{
+    frames::header::{ChannelBits, FrameHeader},
+    metadata::{
+        padding::Padding,
+        seek_table::{self, SeekPoint, SeekSpacing, SeekTable},
+        stream_info::StreamInfo,
+        MetaDataBlockType, FLAC_MARKER,
+    },
+    utils::{crc16_remainder, Crc16Sink},
+    BitSinkAdapter, ByteSink, Error, SeekableByteSink, SliceByteSink,
+};
+
+#[cfg(feature = "verify")]
+pub use verify::VerifyWorkspace;
+pub use workspace::{EncoderWorkspace, MAX_LPC_ORDER};
+
+/// Encodes a fixed-blocksize stream one block at a time.
+///
+/// Every block must hold `BLOCK_SIZE` samples per channel, except the last
+/// one, which may be shorter. Passing a short block ends the stream.
+///
+/// Each subframe is written as the smallest of the constant, verbatim, fixed
+/// and LPC encodings, worked out in the borrowed [`EncoderWorkspace`] so
+/// nothing large lives on the stack.
+///
+/// When the stream header is written through
+/// [`write_metadata`](Self::write_metadata), [`finish`](Self::finish) can go
+/// back and fill in STREAMINFO and the seek table once every frame is known.
+pub struct Encoder<'w, const CHANNELS: usize, const BLOCK_SIZE: usize> {
+    workspace: &'w mut EncoderWorkspace<BLOCK_SIZE>,
+    stream_info: StreamInfo,
+    sample_rate: u32,
+    bit_depth: u8,
+    channel_bits: ChannelBits,
+    max_lpc_order: usize,
+    frame_number: u64,
+    finished: bool,
+    seek_points: &'w mut [SeekPoint],
+    seek_spacing: u64,
+    next_seek_point: usize,
+    frames_length: u64,
+    metadata_length: Option<u64>,
+    padding: Option<Padding>,
+    #[cfg(feature = "verify")]
+    verify: Option<(&'w mut VerifyWorkspace<CHANNELS, BLOCK_SIZE>, &'w mut [u8])>,
+}
+
+impl<'w, const CHANNELS: usize, const BLOCK_SIZE: usize> Encoder<'w, CHANNELS, BLOCK_SIZE> {
+    pub fn new(
+        workspace: &'w mut EncoderWorkspace<BLOCK_SIZE>,
+        sample_rate: u32,
+        bit_depth: u8,
+    ) -> Result<Self, Error> {
+        let channel_bits =
+            ChannelBits::from_channels(CHANNELS).ok_or(Error::InvalidChannelCount)?;
+        if !(4..=32).contains(&bit_depth) {
+            return Err(Error::InvalidBitDepth);
+        }
+        if BLOCK_SIZE == 0 || BLOCK_SIZE > usize::from(u16::MAX) {
+            return Err(Error::InvalidBlockSize);
+        }
+        #[allow(clippy::cast_possible_truncation)]
+        Ok(Self {
+            workspace,
+            stream_info: StreamInfo::new(sample_rate, CHANNELS as u8, bit_depth),
+            sample_rate,
+            bit_depth,
+            channel_bits,
+            max_lpc_order: 8,
+            frame_number: 0,
+            finished: false,
+            seek_points: &mut [],
+            seek_spacing: 0,
+            next_seek_point: 0,
+            frames_length: 0,
+            metadata_length: None,
+            padding: None,
+            #[cfg(feature = "verify")]
+            verify: None,
+        })
+    }
+
+    /// Highest LPC order tried for each subframe, 8 by default.
+    ///
+    /// Encoding time grows with the order; 0 disables LPC altogether.
+    pub fn with_max_lpc_order(mut self, max_lpc_order: usize) -> Self {
+        self.max_lpc_order = max_lpc_order.min(MAX_LPC_ORDER);
+        self
+    }
+
+    /// Writes a SEEKTABLE of `seek_points.len()` points, one every `spacing`.
+    ///
+    /// Points the stream is too short to use stay placeholders, so a table
+    /// sized for the longest recording can be reserved up front.
+    pub fn with_seek_table(
+        mut self,
+        seek_points: &'w mut [SeekPoint],
+        spacing: SeekSpacing,
+    ) -> Self {
+        seek_points.fill(SeekPoint::placeholder());
+        self.seek_points = seek_points;
+        self.seek_spacing = spacing.samples(self.sample_rate);
+        self
+    }
+
+    /// Ends the stream header with `length` bytes of PADDING, so tags can
+    /// later be edited in place without rewriting the whole file.
+    pub fn with_padding(mut self, length: usize) -> Self {
+        self.padding = Some(Padding::new(length));
+        self
+    }
+
+    /// Decodes every frame right after encoding it and compares it with the
+    /// input, failing with [`Error::VerifyMismatch`] on the first sample that
+    /// differs. The frame is then neither written nor counted.
+    ///
+    /// [`encode`](Self::encode) builds each frame in `frame` before writing
+    /// it out, so `frame` must hold one of
+    /// [`budget::max_frame_size`](crate::budget::max_frame_size) bytes.
+    #[cfg(feature = "verify")]
+    pub fn with_verify(
+        mut self,
+        workspace: &'w mut VerifyWorkspace<CHANNELS, BLOCK_SIZE>,
+        frame: &'w mut [u8],
+    ) -> Self {
+        self.verify = Some((workspace, frame));
+        self
+    }
+
+    /// Writes the `fLaC` marker, STREAMINFO, the seek table if any, `blocks`
+    /// and the padding if any.
+    ///
+    /// STREAMINFO and the seek table are placeholders until
+    /// [`finish`](Self::finish) overwrites them.
+    pub fn write_metadata<BS: ByteSink>(
+        &mut self,
+        sink: &mut BS,
+        blocks: &[MetaDataBlockType<'_>],
+    ) -> Result<(), Error> {
+        let stream_info = MetaDataBlockType::StreamInfo(self.stream_info);
+        let seek_table = MetaDataBlockType::SeekTable(SeekTable::new(self.seek_points));
+        let seek_table = (!self.seek_points.is_empty()).then_some(&seek_table);
+        let padding = self.padding.map(MetaDataBlockType::Padding);
+        for block in blocks {
+            match block {
+                MetaDataBlockType::StreamInfo(_) => return Err(Error::DuplicateStreamInfo),
+                MetaDataBlockType::SeekTable(_) if seek_table.is_some() => {
+                    return Err(Error::DuplicateSeekTable)
+                }
+                _ => {}
+            }
+        }
+        for block in seek_table.into_iter().chain(blocks).chain(&padding) {
+            block.check_len()?;
+        }
+        sink.write_bytes(&FLAC_MARKER);
+        let mut length = FLAC_MARKER.len();
+        let mut blocks = core::iter::once(&stream_info)
+            .chain(seek_table)
+            .chain(blocks)
+            .chain(&padding)
+            .peekable();
+        while let Some(block) = blocks.next() {
+            block.write_block(blocks.peek().is_none(), sink)?;
+            length += 4 + block.len();
+        }
+        self.metadata_length = Some(length as u64);
+        Ok(())
+    }
+
+    /// Encodes one block, `samples` holding one slice per channel.
+    ///
+    /// The samples are read in place, so `i16` or `i32` DMA buffers can be
+    /// passed directly.
+    pub fn encode<BS: ByteSink, S: Copy + Into<i32>>(
+        &mut self,
+        sink: &mut BS,
+        samples: [&[S]; CHANNELS],
+    ) -> Result<(), Error> {
+        let block_size = self.check_block(&samples)?;
+        #[cfg(feature = "verify")]
+        if let Some((workspace, frame)) = self.verify.take() {
+            let result = self
+                .write_frame_into(block_size, samples, frame)
+                .and_then(|length| {
+                    let frame = &frame[..length];
+                    workspace.check(&self.stream_info, self.frame_number, frame, &samples)?;
+                    sink.write_bytes(frame);
+                    self.added_frame(block_size, length);
+                    Ok(())
+                });
+            self.verify = Some((workspace, frame));
+            return result;
+        }
+        let mut crc_sink = Crc16Sink::new(sink);
+        self.write_frame_body(&mut crc_sink, block_size, samples);
+        let crc = crc_sink.crc();
+        let frame_size = crc_sink.length() + 2;
+        crc.to_be_bytes().iter().for_each(|&byte| sink.write(byte));
+        self.added_frame(block_size, frame_size);
+        Ok(())
+    }
+
+    /// Encodes one block as a complete frame at the start of `out`, returning
+    /// its length.
+    ///
+    /// If the frame does not fit, [`Error::BufferTooSmall`] is returned and
+    /// the encoder is left as it was, so the block can be retried with a
+    /// bigger buffer. One of
+    /// [`budget::max_frame_size`](crate::budget::max_frame_size) bytes is
+    /// always enough.
+    pub fn encode_frame_into<S: Copy + Into<i32>>(
+        &mut self,
+        samples: [&[S]; CHANNELS],
+        out: &mut [u8],
+    ) -> Result<usize, Error> {
+        let block_size = self.check_block(&samples)?;
+        let length = self.write_frame_into(block_size, samples, out)?;
+        #[cfg(feature = "verify")]
+        if let Some((workspace, _)) = &mut self.verify {
+            let frame = &out[..length];
+            workspace.check(&self.stream_info, self.frame_number, frame, &samples)?;
+        }
+        self.added_frame(block_size, length);
+        Ok(length)
+    }
+
+    /// Writes a whole frame at the start of `out` without counting it,
+    /// returning its length.
+    fn write_frame_into<S: Copy + Into<i32>>(
+        &mut self,
+        block_size: usize,
+        samples: [&[S]; CHANNELS],
+        out: &mut [u8],
+    ) -> Result<usize, Error> {
+        const CRC_POLYNOMIAL: u16 = 0b1000_0000_0000_0101;
+        const CRC_INITIAL: u16 = 0b0000_0000_0000_0000;
+        let mut sink = SliceByteSink::new(out);
+        self.write_frame_body(&mut sink, block_size, samples);
+        let length = sink.len();
+        if sink.overflowed() || out.len() - length < 2 {
+            return Err(Error::BufferTooSmall);
+        }
+        let crc = crc16_remainder(&out[..length], CRC_POLYNOMIAL, CRC_INITIAL);
+        out[length..length + 2].copy_from_slice(&crc.to_be_bytes());
+        Ok(length + 2)
+    }
+
+    fn check_block<S>(&self, samples: &[&[S]; CHANNELS]) -> Result<usize, Error> {
+        if self.finished {
+            return Err(Error::StreamFinished);
+        }
+        let block_size = samples.first().map_or(0, |channel| channel.len());
+        if block_size == 0
+            || block_size > BLOCK_SIZE
+            || samples.iter().any(|channel| channel.len() != block_size)
+        {
+            return Err(Error::InvalidBlockSize);
+        }
+        Ok(block_size)
+    }
+
+    /// Writes the frame header and subframes, everything but the CRC-16.
+    fn write_frame_body<BS: ByteSink, S: Copy + Into<i32>>(
+        &mut self,
+        sink: &mut BS,
+        block_size: usize,
+        samples: [&[S]; CHANNELS],
+    ) {
+        #[allow(clippy::cast_possible_truncation)]
+        let header = FrameHeader::new_fixed_size(
+            block_size as u16,
+            self.sample_rate,
+            self.channel_bits,
+            self.bit_depth,
+            self.frame_number,
+        );
+        header.write(sink);
+        let mut bit_sink = BitSinkAdapter::new(sink);
+        for channel in samples {
+            self.workspace.write_sub_frame(
+                channel,
+                self.bit_depth,
+                self.max_lpc_order,
+                &mut bit_sink,
+            );
+        }
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn added_frame(&mut self, block_size: usize, frame_size: usize) {
+        if block_size < BLOCK_SIZE && self.frame_number != 0 {
+            self.stream_info.added_last_block_with(block_size as u16);
+        } else {
+            self.stream_info.added_block_with(block_size as u16);
+        }
+        self.stream_info.added_frame_with(frame_size as u32);
+        let first_sample = self.frame_number * BLOCK_SIZE as u64;
+        let next_frame = first_sample + block_size as u64;
+        while let Some(point) = self.seek_points.get_mut(self.next_seek_point) {
+            if (self.next_seek_point as u64).saturating_mul(self.seek_spacing) >= next_frame {
+                break;
+            }
+            *point = SeekPoint::new(first_sample, self.frames_length, block_size as u16);
+            self.next_seek_point += 1;
+        }
+        self.frames_length += frame_size as u64;
+        self.frame_number += 1;
+        self.finished = block_size < BLOCK_SIZE;
+    }
+
+    /// Ends the stream and overwrites STREAMINFO and the seek table written
+    /// by [`write_metadata`](Self::write_metadata) with their final values.
+    ///
+    /// `sink` must be positioned right after the last frame, with the
+    /// metadata and every frame written to it back to back.
+    pub fn finish<BS: SeekableByteSink>(&mut self, sink: &mut BS) -> Result<(), Error> {
+        let metadata_length = self.metadata_length.ok_or(Error::MissingMetadata)?;
+        let end = sink.position();
+        let start = end
+            .checked_sub(metadata_length + self.frames_length)
+            .ok_or(Error::MissingMetadata)?;
+        seek_table::normalize(self.seek_points);
+        let stream_info_start = start + FLAC_MARKER.len() as u64 + 4;
+        sink.seek(stream_info_start);
+        self.stream_info.write(sink);
+        if !self.seek_points.is_empty() {
+            sink.seek(stream_info_start + StreamInfo::LENGTH as u64 + 4);
+            SeekTable::new(self.seek_points).write(sink);
+        }
+        sink.seek(end);
+        self.finished = true;
+        Ok(())
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    pub fn stream_info(&self) -> &StreamInfo {
+        &self.stream_info
+    }
+}
+
+#[cfg(feature = "verify")]
+mod verify;
+mod workspace;
+
+#[cfg(test)]
+mod tests {
+    use super::{Encoder, EncoderWorkspace};
+    use crate::{
+        decoder::FrameDecoder,
+        metadata::seek_table::{SeekPoint, SeekSpacing},
+        BufferByteSink, Error,
+    };
+
+    #[test]
+    fn frame_into_small_buffer() {
+        let ramp: [i32; 64] = core::array::from_fn(|i| (i * i) as i32);
+        let mut workspace: EncoderWorkspace<64> = EncoderWorkspace::new();
+        let mut encoder: Encoder<1, 64> = Encoder::new(&mut workspace, 8_000, 16).unwrap();
+        let mut out = [0; 8];
+        assert_eq!(
+            encoder.encode_frame_into([&ramp], &mut out),
+            Err(Error::BufferTooSmall)
+        );
+        let mut out = [0; 256];
+        let length = encoder.encode_frame_into([&ramp], &mut out).unwrap();
+
+        let mut workspace: EncoderWorkspace<64> = EncoderWorkspace::new();
+        let mut encoder: Encoder<1, 64> = Encoder::new(&mut workspace, 8_000, 16).unwrap();
+        let mut sink: BufferByteSink<256> = BufferByteSink::new();
+        encoder.encode(&mut sink, [&ramp]).unwrap();
+        assert_eq!(&out[..length], sink.as_slice());
+    }
+
+    #[test]
+    fn seek_table_filled_on_finish() {
+        let ramp: [i32; 32] = core::array::from_fn(|i| (i * i) as i32);
+        let mut workspace: EncoderWorkspace<32> = EncoderWorkspace::new();
+        let mut seek_points = [SeekPoint::new(1, 2, 3); 8];
+        let mut encoder: Encoder<1, 32> = Encoder::new(&mut workspace, 8_000, 16)
+            .unwrap()
+            .with_seek_table(&mut seek_points, SeekSpacing::Samples(16))
+            .with_padding(16);
+        let mut sink: BufferByteSink<1024> = BufferByteSink::new();
+        assert_eq!(encoder.finish(&mut sink), Err(Error::MissingMetadata));
+        encoder.write_metadata(&mut sink, &[]).unwrap();
+        let metadata_length = sink.as_slice().len();
+        assert_eq!(metadata_length, 4 + 4 + 34 + 4 + 8 * 18 + 4 + 16);
+        let mut offsets = [0; 4];
+        for offset in &mut offsets[..3] {
+            *offset = (sink.as_slice().len() - metadata_length) as u64;
+            encoder.encode(&mut sink, [&ramp]).unwrap();
+        }
+        offsets[3] = (sink.as_slice().len() - metadata_length) as u64;
+        encoder.encode(&mut sink, [&ramp[..10]]).unwrap();
+        encoder.finish(&mut sink).unwrap();
+        let stream_info = encoder.stream_info();
+        assert_eq!(
+            (stream_info.min_block_size(), stream_info.max_block_size()),
+            (32, 32)
+        );
+        assert_eq!(stream_info.total_samples(), 32 * 3 + 10);
+
+        let bytes = sink.as_slice();
+        assert_eq!(bytes[4 + 4 + 34], 0b0000_0011);
+        assert_eq!(bytes[metadata_length - 20], 0b1000_0001);
+        assert_eq!(
+            &bytes[4 + 4 + 13..4 + 4 + 18],
+            &[0xF0, 0, 0, 0, 32 * 3 + 10]
+        );
+        let points = &bytes[4 + 4 + 34 + 4..metadata_length - 20];
+        for (i, point) in points.chunks(18).enumerate() {
+            let expected = match offsets.get(i) {
+                Some(&offset) => {
+                    let count = if i == 3 { 10 } else { 32 };
+                    SeekPoint::new(32 * i as u64, offset, count)
+                }
+                None => SeekPoint::placeholder(),
+            };
+            let mut written: BufferByteSink<18> = BufferByteSink::new();
+            expected.write(&mut written);
+            assert_eq!(point, written.as_slice());
+        }
+    }
+
+    #[test]
+    fn full_scale_32_bit() {
+        let noise: [i32; 64] = core::array::from_fn(|i| {
+            let sample = (i as i32).wrapping_mul(0x2F6B_3C1D).rotate_left(i as u32);
+            if i % 3 == 0 {
+                sample | i32::MIN
+            } else {
+                sample | i32::MAX >> 1
+            }
+        });
+        let mut workspace: EncoderWorkspace<64> = EncoderWorkspace::new();
+        let mut encoder: Encoder<1, 64> = Encoder::new(&mut workspace, 48_000, 32).unwrap();
+        let mut out = [0; 512];
+        let length = encoder.encode_frame_into([&noise], &mut out).unwrap();
+        let mut decoded = [0; 64];
+        FrameDecoder::new(encoder.stream_info())
+            .decode(&out[..length], &mut [&mut decoded])
+            .unwrap();
+        assert_eq!(decoded, noise);
+    }
+
+    #[cfg(feature = "verify")]
+    #[test]
+    fn verify_mode() {
+        use super::VerifyWorkspace;
+        use crate::budget::max_frame_size;
+
+        let ramp: [i32; 64] = core::array::from_fn(|i| (i * i) as i32 - 1000);
+        let steps: [i16; 64] = core::array::from_fn(|i| (i / 8) as i16 * 256);
+        let mut workspace: EncoderWorkspace<64> = EncoderWorkspace::new();
+        let mut verify: VerifyWorkspace<2, 64> = VerifyWorkspace::new();
+        let mut frame = [0; max_frame_size::<2, 64>(16)];
+        let mut encoder: Encoder<2, 64> = Encoder::new(&mut workspace, 44_100, 16)
+            .unwrap()
+            .with_verify(&mut verify, &mut frame);
+        let steps = steps.map(i32::from);
+        let mut sink: BufferByteSink<1024> = BufferByteSink::new();
+        encoder.encode(&mut sink, [&ramp, &steps]).unwrap();
+        let mut out = [0; 512];
+        let length = encoder
+            .encode_frame_into([&ramp, &steps], &mut out)
+            .unwrap();
+        let stream_info = *encoder.stream_info();
+        assert_eq!(stream_info.total_samples(), 128);
+        assert_eq!(sink.as_slice().len(), length);
+
+        let mut changed = steps;
+        changed[5] += 1;
+        assert_eq!(
+            verify.check(&stream_info, 1, &out[..length], &[&ramp, &changed]),
+            Err(Error::VerifyMismatch {
+                frame: 1,
+                channel: 1,
+                sample: 5
+            })
+        );
+    }
+}
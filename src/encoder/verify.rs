@@ -0,0 +1,50 @@
+use crate::{decoder::FrameDecoder, metadata::stream_info::StreamInfo, Error};
+
+/// Room for the samples a frame decodes to in verify mode, lent to the
+/// [`Encoder`](super::Encoder) like [`EncoderWorkspace`](super::EncoderWorkspace).
+pub struct VerifyWorkspace<const CHANNELS: usize, const BLOCK_SIZE: usize> {
+    samples: [[i32; BLOCK_SIZE]; CHANNELS],
+}
+
+impl<const CHANNELS: usize, const BLOCK_SIZE: usize> VerifyWorkspace<CHANNELS, BLOCK_SIZE> {
+    pub const fn new() -> Self {
+        Self {
+            samples: [[0; BLOCK_SIZE]; CHANNELS],
+        }
+    }
+
+    /// Decodes `frame` and compares it with `samples`, what it was encoded
+    /// from.
+    pub(crate) fn check<S: Copy + Into<i32>>(
+        &mut self,
+        stream_info: &StreamInfo,
+        frame_number: u64,
+        frame: &[u8],
+        samples: &[&[S]; CHANNELS],
+    ) -> Result<(), Error> {
+        let mut channels = self.samples.each_mut().map(|channel| &mut channel[..]);
+        FrameDecoder::new(stream_info).decode(frame, &mut channels)?;
+        for (channel, (input, decoded)) in samples.iter().zip(&self.samples).enumerate() {
+            if let Some(sample) = input
+                .iter()
+                .zip(decoded)
+                .position(|(&input, &decoded)| input.into() != decoded)
+            {
+                return Err(Error::VerifyMismatch {
+                    frame: frame_number,
+                    channel,
+                    sample,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<const CHANNELS: usize, const BLOCK_SIZE: usize> Default
+    for VerifyWorkspace<CHANNELS, BLOCK_SIZE>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
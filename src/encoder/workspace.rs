@@ -0,0 +1,450 @@
+use crate::{
+    frames::sub_frame::{
+        residual_codeing, signed_bits, signed_fold, unary_code, write_sample, ResidualCodingMethod,
+        RiceParams, FIXED_COEFFICIENTS,
+    },
+    BitSink,
+};
+
+pub const MAX_LPC_ORDER: usize = 32;
+const MAX_PARTITION_ORDER: usize = 8;
+const MAX_PARTITIONS: usize = 1 << MAX_PARTITION_ORDER;
+
+/// Scratch memory used to pick and write the smallest subframe for each
+/// channel, reused from one frame to the next.
+///
+/// It holds two residual buffers of `BLOCK_SIZE` samples, the best candidate
+/// so far and the one being tried, along with their Rice parameters and LPC
+/// coefficients. That is far too much for a small task stack, so the caller
+/// owns it, usually in a `static`, and lends it to the
+/// [`Encoder`](super::Encoder).
+pub struct EncoderWorkspace<const BLOCK_SIZE: usize> {
+    residuals: [[i32; BLOCK_SIZE]; 2],
+    rice_params: [[RiceParams; MAX_PARTITIONS]; 2],
+    coefficients: [[i32; MAX_LPC_ORDER]; 2],
+    partition_sums: [u64; MAX_PARTITIONS],
+    partition_bits: [u8; MAX_PARTITIONS],
+    autocorrelation: [f64; MAX_LPC_ORDER + 1],
+    lpc: [f64; MAX_LPC_ORDER],
+    best: Candidate,
+}
+
+#[derive(Clone, Copy)]
+struct Candidate {
+    slot: usize,
+    prediction: Prediction,
+    bits: u64,
+    partition_order: u8,
+    method: ResidualCodingMethod,
+}
+
+#[derive(Clone, Copy)]
+enum Prediction {
+    Verbatim,
+    Fixed(usize),
+    Lpc {
+        order: usize,
+        precision: u8,
+        shift: u8,
+    },
+}
+
+impl<const BLOCK_SIZE: usize> EncoderWorkspace<BLOCK_SIZE> {
+    pub const fn new() -> Self {
+        Self {
+            residuals: [[0; BLOCK_SIZE]; 2],
+            rice_params: [[RiceParams::Param(0); MAX_PARTITIONS]; 2],
+            coefficients: [[0; MAX_LPC_ORDER]; 2],
+            partition_sums: [0; MAX_PARTITIONS],
+            partition_bits: [0; MAX_PARTITIONS],
+            autocorrelation: [0.0; MAX_LPC_ORDER + 1],
+            lpc: [0.0; MAX_LPC_ORDER],
+            best: Candidate {
+                slot: 0,
+                prediction: Prediction::Verbatim,
+                bits: u64::MAX,
+                partition_order: 0,
+                method: ResidualCodingMethod::Rice4Bits,
+            },
+        }
+    }
+
+    /// Writes `samples` as the smallest of the constant, verbatim, fixed and
+    /// LPC subframes, trying LPC orders up to `max_lpc_order`.
+    ///
+    /// `samples` must not be longer than `BLOCK_SIZE`.
+    pub(crate) fn write_sub_frame<S: Copy + Into<i32>>(
+        &mut self,
+        samples: &[S],
+        bit_depth: u8,
+        max_lpc_order: usize,
+        bit_sink: &mut impl BitSink,
+    ) {
+        let first: i32 = samples[0].into();
+        let (all_bits, constant) =
+            samples
+                .iter()
+                .fold((0, true), |(all_bits, constant), &sample| {
+                    let sample: i32 = sample.into();
+                    (all_bits | sample, constant && sample == first)
+                });
+        if constant {
+            bit_sink.write(0b000_000 << 1, 8);
+            write_sample(first, bit_sink, bit_depth, 0);
+            return;
+        }
+        #[allow(clippy::cast_possible_truncation)]
+        let wasted_bits = (all_bits.trailing_zeros() as u8).min(bit_depth - 1);
+        let depth = u64::from(bit_depth - wasted_bits);
+        let length = samples.len();
+
+        self.best = Candidate {
+            slot: 0,
+            prediction: Prediction::Verbatim,
+            bits: length as u64 * depth,
+            partition_order: 0,
+            method: ResidualCodingMethod::Rice4Bits,
+        };
+        for order in 0..FIXED_COEFFICIENTS.len().min(length) {
+            self.try_fixed(samples, wasted_bits, depth, order);
+        }
+        if max_lpc_order > 0 && length > 1 {
+            self.try_lpc(samples, wasted_bits, depth, max_lpc_order.min(length - 1));
+        }
+        self.write_best(samples, bit_depth, wasted_bits, bit_sink);
+    }
+
+    fn try_fixed<S: Copy + Into<i32>>(
+        &mut self,
+        samples: &[S],
+        wasted_bits: u8,
+        depth: u64,
+        order: usize,
+    ) {
+        let slot = 1 - self.best.slot;
+        if !self.predict(samples, wasted_bits, slot, FIXED_COEFFICIENTS[order], 0) {
+            return;
+        }
+        let (bits, partition_order, method) = self.rice_partitions(slot, order, samples.len());
+        self.consider(Candidate {
+            slot,
+            prediction: Prediction::Fixed(order),
+            bits: bits + order as u64 * depth,
+            partition_order,
+            method,
+        });
+    }
+
+    fn try_lpc<S: Copy + Into<i32>>(
+        &mut self,
+        samples: &[S],
+        wasted_bits: u8,
+        depth: u64,
+        max_order: usize,
+    ) {
+        let max_order = max_order.min(MAX_LPC_ORDER);
+        self.autocorrelate(samples, wasted_bits, max_order);
+        let mut error = self.autocorrelation[0];
+        if error <= 0.0 {
+            return;
+        }
+        let precision = lpc_precision(samples.len());
+        self.lpc = [0.0; MAX_LPC_ORDER];
+        for i in 0..max_order {
+            let mut reflection = -self.autocorrelation[i + 1];
+            for j in 0..i {
+                reflection -= self.lpc[j] * self.autocorrelation[i - j];
+            }
+            reflection /= error;
+            self.lpc[i] = reflection;
+            for j in 0..i / 2 {
+                let tmp = self.lpc[j];
+                self.lpc[j] += reflection * self.lpc[i - 1 - j];
+                self.lpc[i - 1 - j] += reflection * tmp;
+            }
+            if i % 2 == 1 {
+                self.lpc[i / 2] += self.lpc[i / 2] * reflection;
+            }
+            error *= 1.0 - reflection * reflection;
+
+            let order = i + 1;
+            let slot = 1 - self.best.slot;
+            let Some(shift) = self.quantize(slot, order, precision) else {
+                continue;
+            };
+            let mut coefficients = [0; MAX_LPC_ORDER];
+            for (coefficient, &quantized) in coefficients.iter_mut().zip(&self.coefficients[slot]) {
+                *coefficient = i64::from(quantized);
+            }
+            if !self.predict(samples, wasted_bits, slot, &coefficients[..order], shift) {
+                continue;
+            }
+            let (bits, partition_order, method) = self.rice_partitions(slot, order, samples.len());
+            let header_bits = 4 + 5 + order as u64 * (depth + u64::from(precision));
+            self.consider(Candidate {
+                slot,
+                prediction: Prediction::Lpc {
+                    order,
+                    precision,
+                    shift,
+                },
+                bits: bits + header_bits,
+                partition_order,
+                method,
+            });
+            if error <= 0.0 {
+                break;
+            }
+        }
+    }
+
+    fn consider(&mut self, candidate: Candidate) {
+        if candidate.bits < self.best.bits {
+            self.best = candidate;
+        }
+    }
+
+    /// Fills `residuals[slot]` from index `coefficients.len()` on, returning
+    /// `false` if a residual does not fit in 32 bits.
+    ///
+    /// `coefficients` apply to the preceding samples, newest first.
+    fn predict<S: Copy + Into<i32>>(
+        &mut self,
+        samples: &[S],
+        wasted_bits: u8,
+        slot: usize,
+        coefficients: &[i64],
+        shift: u8,
+    ) -> bool {
+        let sample = |i: usize| i64::from(samples[i].into() >> wasted_bits);
+        let order = coefficients.len();
+        for i in order..samples.len() {
+            let prediction = coefficients
+                .iter()
+                .enumerate()
+                .map(|(j, &coefficient)| coefficient * sample(i - 1 - j))
+                .sum::<i64>()
+                >> shift;
+            let Ok(residual) = i32::try_from(sample(i) - prediction) else {
+                return false;
+            };
+            self.residuals[slot][i] = residual;
+        }
+        true
+    }
+
+    /// Finds the cheapest Rice partition order for `residuals[slot]`, leaving
+    /// its parameters in `rice_params[slot]`.
+    ///
+    /// Returns the size of the residual section in bits, the partition order
+    /// and the coding method.
+    fn rice_partitions(
+        &mut self,
+        slot: usize,
+        order: usize,
+        length: usize,
+    ) -> (u64, u8, ResidualCodingMethod) {
+        let mut max_partition_order = 0;
+        while max_partition_order < MAX_PARTITION_ORDER
+            && length.is_multiple_of(2 << max_partition_order)
+            && length >> (max_partition_order + 1) > order
+        {
+            max_partition_order += 1;
+        }
+        let partition_size = length >> max_partition_order;
+        for partition in 0..1 << max_partition_order {
+            let start = if partition == 0 {
+                order
+            } else {
+                partition * partition_size
+            };
+            let residuals = &self.residuals[slot][start..(partition + 1) * partition_size];
+            self.partition_sums[partition] = residuals
+                .iter()
+                .map(|&residual| signed_fold(residual))
+                .sum();
+            self.partition_bits[partition] = residuals
+                .iter()
+                .map(|&residual| signed_bits(residual))
+                .max()
+                .unwrap_or(0);
+        }
+
+        let mut best = (u64::MAX, 0, ResidualCodingMethod::Rice4Bits);
+        for partition_order in (0..=max_partition_order).rev() {
+            let partitions = 1 << partition_order;
+            let partition_size = (length >> partition_order) as u64;
+            let mut bits = 2 + 4;
+            let mut method = ResidualCodingMethod::Rice4Bits;
+            for partition in 0..partitions {
+                let count = if partition == 0 {
+                    partition_size - order as u64
+                } else {
+                    partition_size
+                };
+                let (params, partition_bits) = RiceParams::for_partition(
+                    count,
+                    self.partition_sums[partition],
+                    self.partition_bits[partition],
+                );
+                bits += partition_bits;
+                if let ResidualCodingMethod::Rice5Bits = params.method() {
+                    method = ResidualCodingMethod::Rice5Bits;
+                }
+            }
+            bits += partitions as u64 * (method as u64 + 4);
+            if bits < best.0 {
+                for partition in 0..partitions {
+                    let count = if partition == 0 {
+                        partition_size - order as u64
+                    } else {
+                        partition_size
+                    };
+                    self.rice_params[slot][partition] = RiceParams::for_partition(
+                        count,
+                        self.partition_sums[partition],
+                        self.partition_bits[partition],
+                    )
+                    .0;
+                }
+                #[allow(clippy::cast_possible_truncation)]
+                {
+                    best = (bits, partition_order as u8, method);
+                }
+            }
+            for partition in 0..partitions / 2 {
+                self.partition_sums[partition] =
+                    self.partition_sums[2 * partition] + self.partition_sums[2 * partition + 1];
+                self.partition_bits[partition] =
+                    self.partition_bits[2 * partition].max(self.partition_bits[2 * partition + 1]);
+            }
+        }
+        best
+    }
+
+    /// Welch-windowed autocorrelation for lags `0..=max_order`.
+    #[allow(clippy::cast_precision_loss)]
+    fn autocorrelate<S: Copy + Into<i32>>(
+        &mut self,
+        samples: &[S],
+        wasted_bits: u8,
+        max_order: usize,
+    ) {
+        let half = (samples.len() - 1) as f64 / 2.0;
+        let windowed = |i: usize| {
+            let x = (i as f64 - half) / half;
+            f64::from(samples[i].into() >> wasted_bits) * (1.0 - x * x)
+        };
+        for lag in 0..=max_order {
+            self.autocorrelation[lag] = (lag..samples.len())
+                .map(|i| windowed(i) * windowed(i - lag))
+                .sum();
+        }
+    }
+
+    /// Quantizes the predictor of order `order` left in `lpc` into
+    /// `coefficients[slot]`, returning the shift to apply to the prediction.
+    #[allow(clippy::cast_possible_truncation)]
+    fn quantize(&mut self, slot: usize, order: usize, precision: u8) -> Option<u8> {
+        let max = self.lpc[..order]
+            .iter()
+            .fold(0.0_f64, |max, &coefficient| max.max(coefficient.abs()));
+        if max <= 0.0 {
+            return None;
+        }
+        let exponent = ((max.to_bits() >> 52) & 0x7FF) as i32 - 1023;
+        let shift = (i32::from(precision) - 2 - exponent).min(15);
+        let shift = u8::try_from(shift).ok()?;
+        let limit = 1_i64 << (precision - 1);
+        let mut error = 0.0;
+        for (quantized, &coefficient) in self.coefficients[slot].iter_mut().zip(&self.lpc[..order])
+        {
+            error -= coefficient * f64::from(1_u32 << shift);
+            let rounded = if error < 0.0 {
+                error - 0.5
+            } else {
+                error + 0.5
+            } as i64;
+            let clamped = rounded.clamp(-limit, limit - 1);
+            error -= clamped as f64;
+            *quantized = clamped as i32;
+        }
+        Some(shift)
+    }
+
+    fn write_best<S: Copy + Into<i32>>(
+        &self,
+        samples: &[S],
+        bit_depth: u8,
+        wasted_bits: u8,
+        bit_sink: &mut impl BitSink,
+    ) {
+        #[allow(clippy::cast_possible_truncation)]
+        let (sub_frame_type, order) = match self.best.prediction {
+            Prediction::Verbatim => (0b000_001, 0),
+            Prediction::Fixed(order) => (0b001_000 | order as u8, order),
+            Prediction::Lpc { order, .. } => (0b100_000 | (order - 1) as u8, order),
+        };
+        bit_sink.write((sub_frame_type << 1) | u8::from(wasted_bits != 0), 8);
+        if wasted_bits != 0 {
+            unary_code(u64::from(wasted_bits) - 1, bit_sink);
+        }
+        if let Prediction::Verbatim = self.best.prediction {
+            samples.iter().for_each(|&sample| {
+                write_sample(sample.into(), bit_sink, bit_depth, wasted_bits);
+            });
+            return;
+        }
+        samples[..order].iter().for_each(|&sample| {
+            write_sample(sample.into(), bit_sink, bit_depth, wasted_bits);
+        });
+        let slot = self.best.slot;
+        if let Prediction::Lpc {
+            precision, shift, ..
+        } = self.best.prediction
+        {
+            bit_sink.write(precision - 1, 4);
+            bit_sink.write(shift, 5);
+            self.coefficients[slot][..order]
+                .iter()
+                .for_each(|&coefficient| write_sample(coefficient, bit_sink, precision, 0));
+        }
+
+        let method = self.best.method;
+        let partition_order = self.best.partition_order;
+        bit_sink.write(method as u8, 2);
+        bit_sink.write(partition_order, 4);
+        let partition_size = samples.len() >> partition_order;
+        for partition in 0..1 << partition_order {
+            let params = self.rice_params[slot][partition];
+            params.write(bit_sink, method);
+            let start = if partition == 0 {
+                order
+            } else {
+                partition * partition_size
+            };
+            self.residuals[slot][start..(partition + 1) * partition_size]
+                .iter()
+                .for_each(|&residual| residual_codeing(&params, bit_sink, residual));
+        }
+    }
+}
+
+impl<const BLOCK_SIZE: usize> Default for EncoderWorkspace<BLOCK_SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Coefficient precision for a block, as picked by the reference encoder.
+fn lpc_precision(block_size: usize) -> u8 {
+    match block_size {
+        0..=192 => 7,
+        193..=384 => 8,
+        385..=576 => 9,
+        577..=1152 => 10,
+        1153..=2304 => 11,
+        2305..=4608 => 12,
+        _ => 13,
+    }
+}
@@ -0,0 +1,59 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The channel count is not between 1 and 8.
+    InvalidChannelCount,
+    /// The bit depth is not between 4 and 32.
+    InvalidBitDepth,
+    /// A block is empty, longer than the frame allows, or its channels differ in length.
+    InvalidBlockSize,
+    /// A block was passed after the short block that ends the stream.
+    StreamFinished,
+    /// LPC subframes need coefficients, which only the encoder's workspace
+    /// computes, so they cannot be requested for a frame.
+    UnsupportedSubFrameType,
+    /// The output buffer cannot hold what had to be written to it.
+    BufferTooSmall,
+    /// A metadata chain has no room for another block.
+    TooManyBlocks,
+    /// A metadata chain has no STREAMINFO block.
+    MissingStreamInfo,
+    /// A metadata chain already has a STREAMINFO block.
+    DuplicateStreamInfo,
+    /// A stream can only have one SEEKTABLE block.
+    DuplicateSeekTable,
+    /// The stream header was not written by the encoder, so it cannot update
+    /// it.
+    MissingMetadata,
+    /// The Ogg FLAC mapping needs a VORBIS_COMMENT as the first block after
+    /// STREAMINFO.
+    MissingVorbisComment,
+    /// A Vorbis comment field name is empty or not printable ASCII without
+    /// `=`.
+    InvalidFieldName,
+    /// A cue sheet breaks the CUESHEET rules, or the CD-DA ones when it is
+    /// flagged as a CD.
+    InvalidCueSheet,
+    /// Picture data is neither a PNG nor a JPEG whose header can be read.
+    UnsupportedImage,
+    /// Data read back is not a well-formed FLAC stream.
+    InvalidMetadata,
+    /// The data ended in the middle of what was being read.
+    UnexpectedEnd,
+    /// A metadata block payload does not fit in its 24-bit length field.
+    BlockTooLarge,
+    /// An MP4 box, such as the `mdat` of a fragment, does not fit in its
+    /// 32-bit size field.
+    BoxTooLarge,
+    /// A frame header or subframe breaks the FLAC format.
+    InvalidFrame,
+    /// A frame fails its CRC-8 or CRC-16 check.
+    CrcMismatch,
+    /// In verify mode, a frame decoded back to samples that differ from the
+    /// input, the first of them being `sample` of `channel` in frame
+    /// `frame`.
+    VerifyMismatch {
+        frame: u64,
+        channel: usize,
+        sample: usize,
+    },
+}
@@ -1,4 +1,7 @@
-use crate::{utils::crc8_remainder, BufferByteSink, ByteSink};
+use crate::{utils::crc8_remainder, BitSource, BufferByteSink, ByteSink, Error, SliceBitSource};
+
+const CRC_POLYNOMIAL: u8 = 0b0000_0111;
+const CRC_INITIAL: u8 = 0b0000_0000;
 
 pub struct FrameHeader {
     boundary: Boundary,
@@ -44,9 +47,102 @@ impl FrameHeader {
         }
     }
 
+    /// Reads the header at the start of `bytes`, checking its CRC-8.
+    ///
+    /// Reserved codes and a bad sync code give [`Error::InvalidFrame`], a
+    /// wrong checksum [`Error::CrcMismatch`].
+    pub fn parse(bytes: &[u8]) -> Result<Self, Error> {
+        let mut source = SliceBitSource::new(bytes);
+        let mut read = |bits| source.read_bits(bits).ok_or(Error::UnexpectedEnd);
+        let boundary = match read(16)? {
+            0xFFF8 => Boundary::FixedBlockSize,
+            0xFFF9 => Boundary::VariableBlockSize,
+            _ => return Err(Error::InvalidFrame),
+        };
+        let block_size_code = read(4)?;
+        let sample_rate_code = read(4)?;
+        let channel_code = read(4)?;
+        let bit_depth_code = read(3)?;
+        if read(1)? != 0 {
+            return Err(Error::InvalidFrame);
+        }
+        let number = source.read_coded_number().ok_or(Error::InvalidFrame)?;
+        #[allow(clippy::cast_possible_truncation)]
+        let header = Self {
+            boundary,
+            block_size_bits: BlockSizeBits::read(block_size_code as u8, &mut source)?,
+            sample_rate_bits: SampleRateBits::read(sample_rate_code as u8, &mut source)?,
+            channel_bits: ChannelBits::from_u8(channel_code as u8).ok_or(Error::InvalidFrame)?,
+            bit_depth_bits: BitDepthBits::from_code(bit_depth_code as u8)
+                .ok_or(Error::InvalidFrame)?,
+            coded_num: CodedNum::new(number),
+        };
+        let expected = crc8_remainder(
+            &bytes[..source.byte_position()],
+            CRC_POLYNOMIAL,
+            CRC_INITIAL,
+        );
+        if source.read_bits(8).ok_or(Error::UnexpectedEnd)? != u32::from(expected) {
+            return Err(Error::CrcMismatch);
+        }
+        Ok(header)
+    }
+
+    /// Finds the first valid header in `bytes`, returning its offset with
+    /// it. Sync codes that do not start a header passing its CRC-8 are
+    /// skipped.
+    pub fn find(bytes: &[u8]) -> Option<(usize, Self)> {
+        bytes
+            .windows(2)
+            .enumerate()
+            .filter(|(_, sync)| sync[0] == 0xFF && sync[1] & 0xFE == 0xF8)
+            .find_map(|(offset, _)| Some((offset, Self::parse(&bytes[offset..]).ok()?)))
+    }
+
+    /// Bytes the header takes, CRC-8 included.
+    pub fn length(&self) -> usize {
+        let uncommon_block_size = match self.block_size_bits {
+            BlockSizeBits::Uncommon8Bit(_) => 1,
+            BlockSizeBits::Uncommon16Bit(_) => 2,
+            _ => 0,
+        };
+        let uncommon_sample_rate = match self.sample_rate_bits {
+            SampleRateBits::Uncommon8Bit(_) => 1,
+            SampleRateBits::Uncommon16Bit(_) | SampleRateBits::Uncommon16BitDiv10(_) => 2,
+            _ => 0,
+        };
+        4 + usize::from(self.coded_num.length) + uncommon_block_size + uncommon_sample_rate + 1
+    }
+
+    pub fn is_variable_block_size(&self) -> bool {
+        matches!(self.boundary, Boundary::VariableBlockSize)
+    }
+
+    /// Frame number in a fixed-blocksize stream, first sample number
+    /// otherwise.
+    pub fn number(&self) -> u64 {
+        self.coded_num.value()
+    }
+
+    pub fn block_size(&self) -> u32 {
+        self.block_size_bits.block_size()
+    }
+
+    /// `None` when only STREAMINFO has it.
+    pub fn sample_rate(&self) -> Option<u32> {
+        self.sample_rate_bits.sample_rate()
+    }
+
+    pub fn channel_bits(&self) -> ChannelBits {
+        self.channel_bits
+    }
+
+    /// `None` when only STREAMINFO has it.
+    pub fn bit_depth(&self) -> Option<u8> {
+        self.bit_depth_bits.bit_depth()
+    }
+
     pub fn write<BS: ByteSink>(&self, sink: &mut BS) {
-        const CRC_POLYNOMIAL: u8 = 0b0000_0111;
-        const CRC_INITIAL: u8 = 0b0000_0000;
         let mut buff: BufferByteSink<16> = BufferByteSink::new();
         (self.boundary as u16)
             .to_be_bytes()
@@ -106,8 +202,10 @@ enum BlockSizeBits {
 }
 
 impl BlockSizeBits {
+    /// Sizes without a dedicated code are stored as `block_size - 1`, in
+    /// 8 bits up to 256 samples and in 16 bits above that.
     pub fn from_u16(block_size: u16) -> Self {
-        const U8_MAX: u16 = u8::MAX as u16;
+        const U8_LIM: u16 = u8::MAX as u16 + 1;
         #[allow(clippy::cast_possible_truncation)]
         match block_size {
             192 => Self::B192,
@@ -123,14 +221,65 @@ impl BlockSizeBits {
             8192 => Self::B8192,
             16384 => Self::B16384,
             32768 => Self::B32768,
-            0..=U8_MAX => Self::Uncommon8Bit(block_size as u8),
-            _ => Self::Uncommon16Bit(block_size),
+            1..=U8_LIM => Self::Uncommon8Bit((block_size - 1) as u8),
+            _ => Self::Uncommon16Bit(block_size.wrapping_sub(1)),
         }
     }
 
     pub fn as_u8(&self) -> u8 {
         unsafe { *<*const _>::from(self).cast::<u8>() }
     }
+
+    /// Decodes `code`, reading the uncommon size that follows the coded
+    /// number if there is one.
+    fn read(code: u8, source: &mut impl BitSource) -> Result<Self, Error> {
+        #[allow(clippy::cast_possible_truncation)]
+        let mut read = |bits| {
+            source
+                .read_bits(bits)
+                .map(|value| value as u16)
+                .ok_or(Error::UnexpectedEnd)
+        };
+        Ok(match code {
+            0b0001 => Self::B192,
+            0b0010 => Self::B576,
+            0b0011 => Self::B1152,
+            0b0100 => Self::B2304,
+            0b0101 => Self::B4608,
+            #[allow(clippy::cast_possible_truncation)]
+            0b0110 => Self::Uncommon8Bit(read(8)? as u8),
+            0b0111 => Self::Uncommon16Bit(read(16)?),
+            0b1000 => Self::B256,
+            0b1001 => Self::B512,
+            0b1010 => Self::B1024,
+            0b1011 => Self::B2048,
+            0b1100 => Self::B4096,
+            0b1101 => Self::B8192,
+            0b1110 => Self::B16384,
+            0b1111 => Self::B32768,
+            _ => return Err(Error::InvalidFrame),
+        })
+    }
+
+    fn block_size(self) -> u32 {
+        match self {
+            Self::B192 => 192,
+            Self::B576 => 576,
+            Self::B1152 => 1152,
+            Self::B2304 => 2304,
+            Self::B4608 => 4608,
+            Self::Uncommon8Bit(size) => u32::from(size) + 1,
+            Self::Uncommon16Bit(size) => u32::from(size) + 1,
+            Self::B256 => 256,
+            Self::B512 => 512,
+            Self::B1024 => 1024,
+            Self::B2048 => 2048,
+            Self::B4096 => 4096,
+            Self::B8192 => 8192,
+            Self::B16384 => 16384,
+            Self::B32768 => 32768,
+        }
+    }
 }
 
 #[repr(u8)]
@@ -170,9 +319,13 @@ impl SampleRateBits {
             44_100 => Self::KHz44_1,
             48_000 => Self::KHz48,
             96_000 => Self::KHz96,
-            0..U8_LIM => Self::Uncommon8Bit(sample_rate as u8),
-            U8_LIM..U16_LIM => Self::Uncommon16Bit(sample_rate as u16),
-            _ if sample_rate % 10 == 0 => Self::Uncommon16Bit((sample_rate / 10) as u16),
+            _ if sample_rate.is_multiple_of(1000) && sample_rate / 1000 < U8_LIM => {
+                Self::Uncommon8Bit((sample_rate / 1000) as u8)
+            }
+            0..U16_LIM => Self::Uncommon16Bit(sample_rate as u16),
+            _ if sample_rate.is_multiple_of(10) && sample_rate / 10 < U16_LIM => {
+                Self::Uncommon16BitDiv10((sample_rate / 10) as u16)
+            }
             _ => Self::SampleRateOnlyStoredInTheStreaminfoMetadataBlock,
         }
     }
@@ -180,9 +333,60 @@ impl SampleRateBits {
     pub fn as_u8(&self) -> u8 {
         unsafe { *<*const _>::from(self).cast::<u8>() }
     }
+
+    /// Decodes `code`, reading the uncommon rate that follows the block size
+    /// if there is one.
+    fn read(code: u8, source: &mut impl BitSource) -> Result<Self, Error> {
+        #[allow(clippy::cast_possible_truncation)]
+        let mut read = |bits| {
+            source
+                .read_bits(bits)
+                .map(|value| value as u16)
+                .ok_or(Error::UnexpectedEnd)
+        };
+        Ok(match code {
+            0b0000 => Self::SampleRateOnlyStoredInTheStreaminfoMetadataBlock,
+            0b0001 => Self::KHz88_2,
+            0b0010 => Self::KHz176_4,
+            0b0011 => Self::KHz192,
+            0b0100 => Self::KHz8,
+            0b0101 => Self::KHz16,
+            0b0110 => Self::KHz22_05,
+            0b0111 => Self::KHz24,
+            0b1000 => Self::KHz32,
+            0b1001 => Self::KHz44_1,
+            0b1010 => Self::KHz48,
+            0b1011 => Self::KHz96,
+            #[allow(clippy::cast_possible_truncation)]
+            0b1100 => Self::Uncommon8Bit(read(8)? as u8),
+            0b1101 => Self::Uncommon16Bit(read(16)?),
+            0b1110 => Self::Uncommon16BitDiv10(read(16)?),
+            _ => return Err(Error::InvalidFrame),
+        })
+    }
+
+    fn sample_rate(self) -> Option<u32> {
+        Some(match self {
+            Self::SampleRateOnlyStoredInTheStreaminfoMetadataBlock => return None,
+            Self::KHz88_2 => 88_200,
+            Self::KHz176_4 => 176_400,
+            Self::KHz192 => 192_000,
+            Self::KHz8 => 8_000,
+            Self::KHz16 => 16_000,
+            Self::KHz22_05 => 22_050,
+            Self::KHz24 => 24_000,
+            Self::KHz32 => 32_000,
+            Self::KHz44_1 => 44_100,
+            Self::KHz48 => 48_000,
+            Self::KHz96 => 96_000,
+            Self::Uncommon8Bit(rate) => u32::from(rate) * 1000,
+            Self::Uncommon16Bit(rate) => u32::from(rate),
+            Self::Uncommon16BitDiv10(rate) => u32::from(rate) * 10,
+        })
+    }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ChannelBits {
     Mono = 0b0000,
     LeftRight = 0b0001,
@@ -201,6 +405,47 @@ impl ChannelBits {
     pub fn as_u8(&self) -> u8 {
         unsafe { *<*const _>::from(self).cast::<u8>() }
     }
+
+    pub fn from_u8(code: u8) -> Option<Self> {
+        match code {
+            0b1000 => Some(Self::LeftRightStoredAsLeftMinusSideAndStereo),
+            0b1001 => Some(Self::LeftRightStoredAsSideMinusightAndStereo),
+            0b1010 => Some(Self::LeftRightStoredAsMidMinusSideAndStereo),
+            _ => Self::from_channels(usize::from(code) + 1),
+        }
+    }
+
+    pub fn channels(self) -> usize {
+        match self.as_u8() {
+            code @ 0b0000..=0b0111 => usize::from(code) + 1,
+            _ => 2,
+        }
+    }
+
+    /// Independent layout for `channels` channels, if FLAC defines one.
+    pub fn from_channels(channels: usize) -> Option<Self> {
+        match channels {
+            1 => Some(Self::Mono),
+            2 => Some(Self::LeftRight),
+            3 => Some(Self::LeftRightCenter),
+            4 => Some(Self::FrontleftFrontrightBackleftBackright),
+            5 => Some(Self::FrontleftFrontrightFrontcenterBackleftBackright),
+            6 => Some(Self::FrontleftFrontrightFrontcenterLfeBackleftBackright),
+            7 => Some(Self::FrontleftFrontrightFrontcenterLfeBackcenterSideleftSideright),
+            8 => Some(Self::FrontleftFrontrightFrontcenterLfeBackleftBackrightSideleftSideright),
+            _ => None,
+        }
+    }
+
+    /// Index of the side channel, which is coded with one extra bit.
+    pub fn side_channel(self) -> Option<usize> {
+        match self {
+            Self::LeftRightStoredAsLeftMinusSideAndStereo
+            | Self::LeftRightStoredAsMidMinusSideAndStereo => Some(1),
+            Self::LeftRightStoredAsSideMinusightAndStereo => Some(0),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -230,6 +475,31 @@ impl BitDepthBits {
     pub fn as_u8(&self) -> u8 {
         unsafe { *<*const _>::from(self).cast::<u8>() }
     }
+
+    fn from_code(code: u8) -> Option<Self> {
+        match code {
+            0b000 => Some(Self::BitDepthOnlyStoredInTheStreaminfoMetadataBlock),
+            0b001 => Some(Self::BitsPerSample8),
+            0b010 => Some(Self::BitsPerSample12),
+            0b100 => Some(Self::BitsPerSample16),
+            0b101 => Some(Self::BitsPerSample20),
+            0b110 => Some(Self::BitsPerSample24),
+            0b111 => Some(Self::BitsPerSample32),
+            _ => None,
+        }
+    }
+
+    fn bit_depth(self) -> Option<u8> {
+        match self {
+            Self::BitDepthOnlyStoredInTheStreaminfoMetadataBlock => None,
+            Self::BitsPerSample8 => Some(8),
+            Self::BitsPerSample12 => Some(12),
+            Self::BitsPerSample16 => Some(16),
+            Self::BitsPerSample20 => Some(20),
+            Self::BitsPerSample24 => Some(24),
+            Self::BitsPerSample32 => Some(32),
+        }
+    }
 }
 
 struct CodedNum {
@@ -319,6 +589,12 @@ impl CodedNum {
         Self { length, code }
     }
 
+    fn value(&self) -> u64 {
+        SliceBitSource::new(&self.code[..usize::from(self.length)])
+            .read_coded_number()
+            .unwrap_or(0)
+    }
+
     pub fn write<BS: ByteSink>(&self, sink: &mut BS) {
         let length = self.length as usize;
         self.code[0..length]
@@ -326,3 +602,77 @@ impl CodedNum {
             .for_each(|&byte| sink.write(byte));
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{ChannelBits, FrameHeader};
+    use crate::{BufferByteSink, Error};
+
+    #[test]
+    fn uncommon_block_sizes() {
+        let mut sink: BufferByteSink<16> = BufferByteSink::new();
+        FrameHeader::new_fixed_size(100, 44_100, ChannelBits::Mono, 16, 3).write(&mut sink);
+        assert_eq!(&sink.as_slice()[..6], &[0xFF, 0xF8, 0x69, 0x08, 0x03, 99]);
+
+        let mut sink: BufferByteSink<16> = BufferByteSink::new();
+        FrameHeader::new_fixed_size(256, 44_100, ChannelBits::Mono, 16, 3).write(&mut sink);
+        assert_eq!(&sink.as_slice()[..5], &[0xFF, 0xF8, 0x89, 0x08, 0x03]);
+
+        let mut sink: BufferByteSink<16> = BufferByteSink::new();
+        FrameHeader::new_fixed_size(1000, 44_100, ChannelBits::Mono, 16, 3).write(&mut sink);
+        assert_eq!(
+            &sink.as_slice()[..7],
+            &[0xFF, 0xF8, 0x79, 0x08, 0x03, 0x03, 0xE7]
+        );
+    }
+
+    #[test]
+    fn parse_and_find() {
+        let mut sink: BufferByteSink<32> = BufferByteSink::new();
+        let header = FrameHeader::new_variable_size(
+            1000,
+            22_000,
+            ChannelBits::LeftRightStoredAsMidMinusSideAndStereo,
+            24,
+            70_000,
+        );
+        header.write(&mut sink);
+        let bytes = sink.as_slice();
+        assert_eq!(header.length(), bytes.len());
+
+        let parsed = FrameHeader::parse(bytes).unwrap();
+        assert!(parsed.is_variable_block_size());
+        assert_eq!(parsed.number(), 70_000);
+        assert_eq!(parsed.block_size(), 1000);
+        assert_eq!(parsed.sample_rate(), Some(22_000));
+        assert_eq!(
+            parsed.channel_bits(),
+            ChannelBits::LeftRightStoredAsMidMinusSideAndStereo
+        );
+        assert_eq!(parsed.bit_depth(), Some(24));
+        assert_eq!(parsed.length(), bytes.len());
+
+        let mut corrupt = [0; 32];
+        corrupt[..bytes.len()].copy_from_slice(bytes);
+        corrupt[4] ^= 1;
+        assert_eq!(
+            FrameHeader::parse(&corrupt[..bytes.len()]).err(),
+            Some(Error::CrcMismatch)
+        );
+        assert_eq!(
+            FrameHeader::parse(&bytes[..bytes.len() - 1]).err(),
+            Some(Error::UnexpectedEnd)
+        );
+
+        // A false sync code, the corrupt header, then the real one.
+        let mut stream = [0; 64];
+        stream[1..3].copy_from_slice(&[0xFF, 0xF8]);
+        stream[3..3 + bytes.len()].copy_from_slice(&corrupt[..bytes.len()]);
+        let offset = 3 + bytes.len() + 2;
+        stream[offset..offset + bytes.len()].copy_from_slice(bytes);
+        let (found, parsed) = FrameHeader::find(&stream).unwrap();
+        assert_eq!(found, offset);
+        assert_eq!(parsed.number(), 70_000);
+        assert!(FrameHeader::find(&stream[..offset + 4]).is_none());
+    }
+}
@@ -1,14 +1,19 @@
 use header::{ChannelBits, FrameHeader};
-use sub_frame::{SubFrame, SubFrameType};
+use sub_frame::{check_sub_frame, SubFrame, SubFrameRef, SubFrameType};
 
-use crate::{utils::crc16_remainder, BufferByteSink, ByteSink};
+use crate::{utils::Crc16Sink, BitSinkAdapter, ByteSink, Error};
 
-pub struct Frame<const CHANNELS: usize, const BLOCK_SIZE: usize> {
+/// A frame over caller-owned blocks of `BLOCK_SIZE` samples, one per channel.
+///
+/// The samples are borrowed, so building a frame copies nothing.
+pub struct Frame<'a, const CHANNELS: usize, const BLOCK_SIZE: usize, S = i32> {
     header: FrameHeader,
-    subframes: [SubFrame<BLOCK_SIZE>; CHANNELS],
+    subframes: [SubFrame<'a, BLOCK_SIZE, S>; CHANNELS],
 }
 
-impl<const CHANNELS: usize, const BLOCK_SIZE: usize> Frame<CHANNELS, BLOCK_SIZE> {
+impl<'a, const CHANNELS: usize, const BLOCK_SIZE: usize, S: Copy + Into<i32>>
+    Frame<'a, CHANNELS, BLOCK_SIZE, S>
+{
     pub fn new(
         sample_rate: u32,
         channel_bits: ChannelBits,
@@ -16,10 +21,17 @@ impl<const CHANNELS: usize, const BLOCK_SIZE: usize> Frame<CHANNELS, BLOCK_SIZE>
         frame_number: u64,
         sub_frame_header: SubFrameType,
         wasted_bits: u8,
-        samples: [i32; BLOCK_SIZE],
-    ) -> Self {
+        samples: [&'a [S; BLOCK_SIZE]; CHANNELS],
+    ) -> Result<Self, Error> {
+        let side_channel = channel_bits.side_channel();
+        check_sub_frame(sub_frame_header, wasted_bits, bit_depth)?;
+        // The side channel takes one more bit.
+        if side_channel.is_some() {
+            check_sub_frame(sub_frame_header, wasted_bits, bit_depth + 1)?;
+        }
+        let mut channel = 0;
         #[allow(clippy::cast_possible_truncation)]
-        Self {
+        Ok(Self {
             header: FrameHeader::new_fixed_size(
                 BLOCK_SIZE as u16,
                 sample_rate,
@@ -27,27 +39,160 @@ impl<const CHANNELS: usize, const BLOCK_SIZE: usize> Frame<CHANNELS, BLOCK_SIZE>
                 bit_depth,
                 frame_number,
             ),
-            subframes: [SubFrame::new(sub_frame_header, wasted_bits, bit_depth, samples); CHANNELS],
+            subframes: samples.map(|samples| {
+                let bit_depth = bit_depth + u8::from(side_channel == Some(channel));
+                channel += 1;
+                SubFrame::new_checked(sub_frame_header, wasted_bits, bit_depth, samples)
+            }),
+        })
+    }
+
+    /// Returns the number of bytes written, CRC-16 included.
+    ///
+    /// The frame is checksummed as it streams out, so nothing is buffered;
+    /// [`budget::max_frame_size`](crate::budget::max_frame_size) bounds its
+    /// size for subframes no bigger than verbatim.
+    pub fn write<BS: ByteSink>(&self, sink: &mut BS) -> usize {
+        let mut crc_sink = Crc16Sink::new(sink);
+        self.header.write(&mut crc_sink);
+        {
+            let mut bit_sink = BitSinkAdapter::new(&mut crc_sink);
+            self.subframes
+                .iter()
+                .for_each(|&sub_frame| SubFrameRef::from(sub_frame).write(&mut bit_sink));
         }
+        let crc = crc_sink.crc();
+        let length = crc_sink.length();
+        crc.to_be_bytes().iter().for_each(|&byte| sink.write(byte));
+        length + 2
     }
+}
 
-    /// MEM = 16 + `BLOCK_SIZE` * 4 deberia ir
-    pub fn write<BS: ByteSink, const MEM: usize>(&self, sink: &mut BS) {
-        const CRC_POLYNOMIAL: u16 = 0b1000_0000_0000_0101;
-        const CRC_INITIAL: u16 = 0b0000_0000_0000_0000;
-        let mut buff: BufferByteSink<MEM> = BufferByteSink::new();
-        self.header.write(&mut buff);
-        assert_eq!(CHANNELS, 1, "Por ahora solo funca en mono");
-        self.subframes
-            .iter()
-            .for_each(|&sub_frame| sub_frame.write(&mut buff));
-        let crc = crc16_remainder(buff.as_slice(), CRC_POLYNOMIAL, CRC_INITIAL);
-        buff.as_slice()
-            .iter()
-            .for_each(|&byte| sink.write(byte));
+/// A frame over borrowed per-channel samples, with the block size taken from
+/// the length of the slices instead of a const generic.
+///
+/// This is what allows a fixed-blocksize stream to end with a shorter block.
+pub struct FrameRef<'a, const CHANNELS: usize, S = i32> {
+    header: FrameHeader,
+    subframes: [SubFrameRef<'a, S>; CHANNELS],
+}
+
+impl<'a, const CHANNELS: usize, S: Copy + Into<i32>> FrameRef<'a, CHANNELS, S> {
+    /// Every channel must hold the same number of samples, between 1 and
+    /// `u16::MAX`.
+    pub fn new(
+        sample_rate: u32,
+        channel_bits: ChannelBits,
+        bit_depth: u8,
+        frame_number: u64,
+        sub_frame_header: SubFrameType,
+        wasted_bits: u8,
+        samples: [&'a [S]; CHANNELS],
+    ) -> Result<Self, Error> {
+        let block_size = samples.first().map_or(0, |channel| channel.len());
+        if block_size == 0 || samples.iter().any(|channel| channel.len() != block_size) {
+            return Err(Error::InvalidBlockSize);
+        }
+        let block_size = u16::try_from(block_size).map_err(|_| Error::InvalidBlockSize)?;
+        let side_channel = channel_bits.side_channel();
+        check_sub_frame(sub_frame_header, wasted_bits, bit_depth)?;
+        // The side channel takes one more bit.
+        if side_channel.is_some() {
+            check_sub_frame(sub_frame_header, wasted_bits, bit_depth + 1)?;
+        }
+        let mut channel = 0;
+        Ok(Self {
+            header: FrameHeader::new_fixed_size(
+                block_size,
+                sample_rate,
+                channel_bits,
+                bit_depth,
+                frame_number,
+            ),
+            subframes: samples.map(|samples| {
+                let bit_depth = bit_depth + u8::from(side_channel == Some(channel));
+                channel += 1;
+                SubFrameRef::new_checked(sub_frame_header, wasted_bits, bit_depth, samples)
+            }),
+        })
+    }
+
+    /// Returns the number of bytes written, CRC-16 included.
+    pub fn write<BS: ByteSink>(&self, sink: &mut BS) -> usize {
+        let mut crc_sink = Crc16Sink::new(sink);
+        self.header.write(&mut crc_sink);
+        {
+            let mut bit_sink = BitSinkAdapter::new(&mut crc_sink);
+            self.subframes
+                .iter()
+                .for_each(|sub_frame| sub_frame.write(&mut bit_sink));
+        }
+        let crc = crc_sink.crc();
+        let length = crc_sink.length();
         crc.to_be_bytes().iter().for_each(|&byte| sink.write(byte));
+        length + 2
     }
 }
 
 pub mod header;
+pub mod splitter;
 pub mod sub_frame;
+
+#[cfg(test)]
+mod tests {
+    use super::{header::ChannelBits, sub_frame::SubFrameType, FrameRef};
+    use crate::{decoder::FrameDecoder, metadata::stream_info::StreamInfo, BufferByteSink, Error};
+
+    #[test]
+    fn full_scale_32_bit() {
+        let samples: [i32; 16] = core::array::from_fn(|i| {
+            if i % 2 == 0 {
+                i32::MAX
+            } else {
+                i32::MIN + i as i32
+            }
+        });
+        let frame = FrameRef::new(
+            48_000,
+            ChannelBits::Mono,
+            32,
+            0,
+            SubFrameType::FixedPredictorOrder2,
+            0,
+            [&samples[..]],
+        )
+        .unwrap();
+        let mut sink: BufferByteSink<256> = BufferByteSink::new();
+        let length = frame.write(&mut sink);
+        let mut decoded = [0; 16];
+        let info = FrameDecoder::new(&StreamInfo::new(48_000, 1, 32))
+            .decode(sink.as_slice(), &mut [&mut decoded])
+            .unwrap();
+        assert_eq!(info.length, length);
+        assert_eq!(decoded, samples);
+
+        let stereo = |channel_bits, sub_frame_type| {
+            FrameRef::new(
+                48_000,
+                channel_bits,
+                32,
+                0,
+                sub_frame_type,
+                0,
+                [&samples[..], &samples[..]],
+            )
+            .err()
+        };
+        assert_eq!(
+            stereo(
+                ChannelBits::LeftRightStoredAsLeftMinusSideAndStereo,
+                SubFrameType::Verbatim
+            ),
+            Some(Error::InvalidBitDepth)
+        );
+        assert_eq!(
+            stereo(ChannelBits::LeftRight, SubFrameType::LinearPredictorOrder1),
+            Some(Error::UnsupportedSubFrameType)
+        );
+    }
+}
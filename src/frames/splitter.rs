@@ -0,0 +1,226 @@
+//! Splitting a stream into frames without decoding their subframes.
+
+use core::ops::Range;
+
+use super::header::{ChannelBits, FrameHeader};
+use crate::{metadata::stream_info::StreamInfo, utils::crc16_update, Error};
+
+const CRC_POLYNOMIAL: u16 = 0b1000_0000_0000_0101;
+
+/// A frame found by [`FrameSplitter`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SplitFrame {
+    /// Where the frame is in the split bytes, header and CRC-16 included.
+    pub range: Range<usize>,
+    pub sample_number: u64,
+    pub block_size: u32,
+    pub channel_bits: ChannelBits,
+}
+
+/// Iterates over the frames in a run of FLAC frames, for remuxing or
+/// building a seek table after the fact.
+///
+/// A frame ends where a valid header of the same blocking strategy follows
+/// with the CRC-16 of everything before it matching, or at the end of the
+/// bytes. Each byte is read once, through the CRC. Bytes before the first
+/// header are skipped.
+///
+/// A frame whose CRC-16 never matches gives [`Error::CrcMismatch`], and
+/// splitting goes on from the first valid header after its own. When the
+/// maximum frame size is known, the search stops there, so a corrupted
+/// frame costs no more than a good one. A frame
+/// running past the end of the bytes gives [`Error::UnexpectedEnd`] and
+/// ends the iteration, with [`position`](Self::position) left before it so
+/// a caller refilling a buffer can keep the tail.
+pub struct FrameSplitter<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    fixed_block_size: u64,
+    max_frame_size: usize,
+    is_done: bool,
+}
+
+impl<'a> FrameSplitter<'a> {
+    /// Sample numbers in a fixed-blocksize stream are frame numbers times
+    /// the maximum block size of `stream_info`.
+    pub fn new(bytes: &'a [u8], stream_info: &StreamInfo) -> Self {
+        let max_frame_size = match stream_info.max_frame_size() {
+            0 => usize::MAX,
+            size => usize::try_from(size).unwrap_or(usize::MAX),
+        };
+        Self {
+            bytes,
+            position: 0,
+            fixed_block_size: u64::from(stream_info.max_block_size()),
+            max_frame_size,
+            is_done: false,
+        }
+    }
+
+    /// Where the frames not split yet start.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    fn split(&mut self) -> Result<Option<SplitFrame>, Error> {
+        let Some((skipped, header)) = FrameHeader::find(&self.bytes[self.position..]) else {
+            return Ok(None);
+        };
+        let start = self.position + skipped;
+        // The header is followed by at least the CRC-16.
+        let body = start + header.length() + 2;
+        let mut crc = self
+            .bytes
+            .get(start..body)
+            .ok_or(Error::UnexpectedEnd)?
+            .iter()
+            .fold(0, |crc, &byte| crc16_update(crc, byte, CRC_POLYNOMIAL));
+        let mut end = self.bytes.len();
+        // The first header seen, in case the CRC-16 never matches.
+        let mut next_header = None;
+        let mut is_too_long = false;
+        for (position, &byte) in self.bytes.iter().enumerate().skip(body) {
+            if position - start > self.max_frame_size {
+                is_too_long = true;
+                break;
+            }
+            if byte == 0xFF
+                && FrameHeader::parse(&self.bytes[position..]).is_ok_and(|next| {
+                    next.is_variable_block_size() == header.is_variable_block_size()
+                })
+            {
+                if crc == 0 {
+                    end = position;
+                    break;
+                }
+                next_header = next_header.or(Some(position));
+            }
+            crc = crc16_update(crc, byte, CRC_POLYNOMIAL);
+        }
+        if crc != 0 {
+            return match (next_header, is_too_long) {
+                (Some(next_header), _) => {
+                    self.position = next_header;
+                    Err(Error::CrcMismatch)
+                }
+                (None, true) => {
+                    self.position = body;
+                    Err(Error::CrcMismatch)
+                }
+                (None, false) => Err(Error::UnexpectedEnd),
+            };
+        }
+
+        let sample_number = if header.is_variable_block_size() {
+            header.number()
+        } else {
+            header.number() * self.fixed_block_size
+        };
+        self.position = end;
+        Ok(Some(SplitFrame {
+            range: start..end,
+            sample_number,
+            block_size: header.block_size(),
+            channel_bits: header.channel_bits(),
+        }))
+    }
+}
+
+impl Iterator for FrameSplitter<'_> {
+    type Item = Result<SplitFrame, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.is_done {
+            return None;
+        }
+        let frame = self.split().transpose();
+        self.is_done = matches!(frame, None | Some(Err(Error::UnexpectedEnd)));
+        frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FrameSplitter;
+    use crate::{
+        encoder::{Encoder, EncoderWorkspace},
+        Error,
+    };
+
+    #[test]
+    fn splits_encoder_output() {
+        let left: [i32; 64] = core::array::from_fn(|i| (i * i) as i32 - 2000);
+        let right: [i32; 64] = core::array::from_fn(|i| 300 - (i * 7) as i32);
+        let mut workspace: EncoderWorkspace<64> = EncoderWorkspace::new();
+        let mut encoder: Encoder<2, 64> = Encoder::new(&mut workspace, 44_100, 16).unwrap();
+        let mut bytes = [0; 2048];
+        // Leading bytes that are not a frame.
+        bytes[..3].copy_from_slice(&[0x12, 0xFF, 0xF8]);
+        let mut ends = [0; 4];
+        let mut length = 3;
+        for (frame, end) in ends.iter_mut().enumerate() {
+            let block_size = if frame == 3 { 20 } else { 64 };
+            length += encoder
+                .encode_frame_into(
+                    [&left[..block_size], &right[..block_size]],
+                    &mut bytes[length..],
+                )
+                .unwrap();
+            *end = length;
+        }
+
+        let stream_info = *encoder.stream_info();
+        let mut splitter = FrameSplitter::new(&bytes[..length], &stream_info);
+        let mut start = 3;
+        for (frame, &end) in ends.iter().enumerate() {
+            let split = splitter.next().unwrap().unwrap();
+            assert_eq!(split.range, start..end);
+            assert_eq!(split.sample_number, frame as u64 * 64);
+            assert_eq!(split.block_size, if frame == 3 { 20 } else { 64 });
+            assert_eq!(split.channel_bits.channels(), 2);
+            start = end;
+        }
+        assert!(splitter.next().is_none());
+        assert_eq!(splitter.position(), length);
+
+        let mut splitter = FrameSplitter::new(&bytes[..ends[1] - 1], &stream_info);
+        assert_eq!(splitter.next().unwrap().unwrap().range, 3..ends[0]);
+        assert_eq!(splitter.next(), Some(Err(Error::UnexpectedEnd)));
+        assert!(splitter.next().is_none());
+        assert_eq!(splitter.position(), ends[0]);
+
+        // Starting on the short last frame.
+        let mut splitter = FrameSplitter::new(&bytes[ends[2]..length], &stream_info);
+        assert_eq!(splitter.next().unwrap().unwrap().sample_number, 3 * 64);
+
+        // A corrupted frame in the middle.
+        bytes[(ends[0] + ends[1]) / 2] ^= 0x10;
+        let mut splitter = FrameSplitter::new(&bytes[..length], &stream_info);
+        assert_eq!(splitter.next().unwrap().unwrap().range, 3..ends[0]);
+        assert_eq!(splitter.next(), Some(Err(Error::CrcMismatch)));
+        assert_eq!(splitter.position(), ends[1]);
+        assert_eq!(splitter.next().unwrap().unwrap().range, ends[1]..ends[2]);
+        assert_eq!(splitter.next().unwrap().unwrap().range, ends[2]..length);
+        assert!(splitter.next().is_none());
+
+        // Two in a row, the search stopping at the maximum frame size.
+        bytes[(ends[1] + ends[2]) / 2] ^= 0x10;
+        assert!(stream_info.max_frame_size() != 0);
+        let mut splitter = FrameSplitter::new(&bytes[..length], &stream_info);
+        assert_eq!(splitter.next().unwrap().unwrap().range, 3..ends[0]);
+        assert_eq!(splitter.next(), Some(Err(Error::CrcMismatch)));
+        assert_eq!(splitter.position(), ends[1]);
+        assert_eq!(splitter.next(), Some(Err(Error::CrcMismatch)));
+        assert_eq!(splitter.position(), ends[2]);
+        assert_eq!(splitter.next().unwrap().unwrap().range, ends[2]..length);
+        assert!(splitter.next().is_none());
+        // No header within the maximum frame size of a corrupted frame.
+        bytes[ends[1]] = 0;
+        let mut splitter = FrameSplitter::new(&bytes[..length], &stream_info);
+        assert_eq!(splitter.next().unwrap().unwrap().range, 3..ends[0]);
+        assert_eq!(splitter.next(), Some(Err(Error::CrcMismatch)));
+        assert!(splitter.position() < ends[1]);
+        assert_eq!(splitter.next().unwrap().unwrap().range, ends[2]..length);
+        assert!(splitter.next().is_none());
+    }
+}
@@ -1,17 +1,41 @@
-use core::{cmp::min, ops::Sub};
+use core::cmp::min;
 
-use crate::{BitSink, BitSinkAdapter, ByteSink};
+use crate::{BitSink, BitSinkAdapter, ByteSink, Error};
 
+pub(crate) const FIXED_COEFFICIENTS: [&[i64]; 5] =
+    [&[], &[1], &[2, -1], &[3, -3, 1], &[4, -6, 4, -1]];
+
+/// A subframe over a borrowed block of `N` samples.
+///
+/// Samples can be any type that widens to `i32`, so `i16` buffers are
+/// encoded without converting them first.
 #[derive(Clone, Copy)]
-pub struct SubFrame<const N: usize> {
+pub struct SubFrame<'a, const N: usize, S = i32> {
     header: SubFrameType,
     wasted_bits: u8,
     bit_depth: u8,
-    samples: [i32; N],
+    samples: &'a [S; N],
 }
 
-impl<const N: usize> SubFrame<N> {
-    pub fn new(header: SubFrameType, wasted_bits: u8, bit_depth: u8, samples: [i32; N]) -> Self {
+impl<'a, const N: usize, S: Copy + Into<i32>> SubFrame<'a, N, S> {
+    /// Fails like [`SubFrameRef::new`].
+    pub fn new(
+        header: SubFrameType,
+        wasted_bits: u8,
+        bit_depth: u8,
+        samples: &'a [S; N],
+    ) -> Result<Self, Error> {
+        check_sub_frame(header, wasted_bits, bit_depth)?;
+        Ok(Self::new_checked(header, wasted_bits, bit_depth, samples))
+    }
+
+    /// [`new`](Self::new) for arguments [`check_sub_frame`] accepted.
+    pub(super) fn new_checked(
+        header: SubFrameType,
+        wasted_bits: u8,
+        bit_depth: u8,
+        samples: &'a [S; N],
+    ) -> Self {
         Self {
             header,
             wasted_bits,
@@ -20,122 +44,158 @@ impl<const N: usize> SubFrame<N> {
         }
     }
 
-    #[allow(clippy::cast_possible_truncation)]
     pub fn write<BS: ByteSink>(&self, sink: &mut BS) {
+        let mut bit_sink = BitSinkAdapter::new(sink);
+        SubFrameRef::from(*self).write(&mut bit_sink);
+    }
+}
+
+impl<'a, const N: usize, S: Copy + Into<i32>> From<SubFrame<'a, N, S>> for SubFrameRef<'a, S> {
+    fn from(sub_frame: SubFrame<'a, N, S>) -> Self {
+        SubFrameRef::new_checked(
+            sub_frame.header,
+            sub_frame.wasted_bits,
+            sub_frame.bit_depth,
+            sub_frame.samples,
+        )
+    }
+}
+
+/// A subframe over borrowed samples, so its length is only known at runtime.
+///
+/// Subframes are not byte aligned, so this writes to a [`BitSink`] shared by
+/// all the subframes of a frame.
+#[derive(Clone, Copy)]
+pub struct SubFrameRef<'a, S = i32> {
+    header: SubFrameType,
+    wasted_bits: u8,
+    bit_depth: u8,
+    samples: &'a [S],
+}
+
+impl<'a, S: Copy + Into<i32>> SubFrameRef<'a, S> {
+    /// Fixed predictors whose order does not fit in `samples`, or whose
+    /// residuals do not fit in 32 bits, fall back to
+    /// [`SubFrameType::Verbatim`], so any block can be encoded.
+    ///
+    /// Returns [`Error::InvalidBitDepth`] for depths above 32, which a side
+    /// channel of 32-bit audio would need, and
+    /// [`Error::UnsupportedSubFrameType`] for LPC subframes.
+    pub fn new(
+        header: SubFrameType,
+        wasted_bits: u8,
+        bit_depth: u8,
+        samples: &'a [S],
+    ) -> Result<Self, Error> {
+        check_sub_frame(header, wasted_bits, bit_depth)?;
+        Ok(Self::new_checked(header, wasted_bits, bit_depth, samples))
+    }
+
+    /// [`new`](Self::new) for arguments [`check_sub_frame`] accepted.
+    pub(super) fn new_checked(
+        header: SubFrameType,
+        wasted_bits: u8,
+        bit_depth: u8,
+        samples: &'a [S],
+    ) -> Self {
+        let header = match header.fixed_order() {
+            Some(order)
+                if order > samples.len()
+                    || fixed_residuals(samples, order, wasted_bits)
+                        .any(|residual| i32::try_from(residual).is_err()) =>
+            {
+                SubFrameType::Verbatim
+            }
+            _ => header,
+        };
+        Self {
+            header,
+            wasted_bits,
+            bit_depth,
+            samples,
+        }
+    }
+
+    pub fn write(&self, bit_sink: &mut impl BitSink) {
         let wasted_bits_flag = u8::from(self.wasted_bits != 0);
         let header = ((self.header as u8) << 1) | wasted_bits_flag;
-        sink.write(header);
-        let mut bit_sink = BitSinkAdapter::new(sink);
+        bit_sink.write(header, 8);
         if self.wasted_bits != 0 {
-            unary_code(u32::from(self.wasted_bits) - 1, &mut bit_sink);
+            unary_code(u64::from(self.wasted_bits) - 1, bit_sink);
         };
         match self.header {
-            SubFrameType::Constant => (self.samples[0] >> self.wasted_bits)
-                .to_be_bytes()
-                .iter()
-                .for_each(|&byte| bit_sink.write(byte, 8)),
+            SubFrameType::Constant => {
+                write_sample(
+                    self.samples[0].into(),
+                    bit_sink,
+                    self.bit_depth,
+                    self.wasted_bits,
+                );
+            }
             SubFrameType::Verbatim => self.samples.iter().for_each(|&sample| {
-                write_sample(sample, &mut bit_sink, self.bit_depth, self.wasted_bits);
+                write_sample(sample.into(), bit_sink, self.bit_depth, self.wasted_bits);
             }),
-            SubFrameType::FixedPredictorOrder0 => self.wirte_predictor::<0>(
-                ResidualCodingMethod::Rice4Bits,
-                &RiceParams::Param(5),
-                &mut bit_sink,
-                |_, _| 0,
-            ),
-            SubFrameType::FixedPredictorOrder1 => self.wirte_predictor::<1>(
-                ResidualCodingMethod::Rice4Bits,
-                &RiceParams::Param(5),
-                &mut bit_sink,
-                |x, _| x[0],
-            ),
-            SubFrameType::FixedPredictorOrder2 => self.wirte_predictor::<2>(
-                ResidualCodingMethod::Rice4Bits,
-                &RiceParams::Param(5),
-                &mut bit_sink,
-                |x, cero| 2 * x[cero] - x[(cero - 1).rem_euclid(2)],
-            ),
-            SubFrameType::FixedPredictorOrder3 => self.wirte_predictor::<3>(
-                ResidualCodingMethod::Rice4Bits,
-                &RiceParams::Param(5),
-                &mut bit_sink,
-                |x, cero| {
-                    3 * x[cero] - 3 * x[(cero - 1).rem_euclid(3)] + x[(cero - 2).rem_euclid(3)]
-                },
-            ),
-            SubFrameType::FixedPredictorOrder4 => self.wirte_predictor::<4>(
-                ResidualCodingMethod::Rice4Bits,
-                &RiceParams::Param(5),
-                &mut bit_sink,
-                |x, cero| {
-                    4 * x[cero] - 6 * x[(cero - 1).rem_euclid(4)] + 4 * x[(cero - 2).rem_euclid(4)]
-                        - x[(cero - 3).rem_euclid(4)]
-                },
-            ),
-            SubFrameType::LinearPredictorOrder1 => todo!(),
-            SubFrameType::LinearPredictorOrder2 => todo!(),
-            SubFrameType::LinearPredictorOrder3 => todo!(),
-            SubFrameType::LinearPredictorOrder4 => todo!(),
-            SubFrameType::LinearPredictorOrder5 => todo!(),
-            SubFrameType::LinearPredictorOrder6 => todo!(),
-            SubFrameType::LinearPredictorOrder7 => todo!(),
-            SubFrameType::LinearPredictorOrder8 => todo!(),
-            SubFrameType::LinearPredictorOrder9 => todo!(),
-            SubFrameType::LinearPredictorOrder10 => todo!(),
-            SubFrameType::LinearPredictorOrder11 => todo!(),
-            SubFrameType::LinearPredictorOrder12 => todo!(),
-            SubFrameType::LinearPredictorOrder13 => todo!(),
-            SubFrameType::LinearPredictorOrder14 => todo!(),
-            SubFrameType::LinearPredictorOrder15 => todo!(),
-            SubFrameType::LinearPredictorOrder16 => todo!(),
-            SubFrameType::LinearPredictorOrder17 => todo!(),
-            SubFrameType::LinearPredictorOrder18 => todo!(),
-            SubFrameType::LinearPredictorOrder19 => todo!(),
-            SubFrameType::LinearPredictorOrder20 => todo!(),
-            SubFrameType::LinearPredictorOrder21 => todo!(),
-            SubFrameType::LinearPredictorOrder22 => todo!(),
-            SubFrameType::LinearPredictorOrder23 => todo!(),
-            SubFrameType::LinearPredictorOrder24 => todo!(),
-            SubFrameType::LinearPredictorOrder25 => todo!(),
-            SubFrameType::LinearPredictorOrder26 => todo!(),
-            SubFrameType::LinearPredictorOrder27 => todo!(),
-            SubFrameType::LinearPredictorOrder28 => todo!(),
-            SubFrameType::LinearPredictorOrder29 => todo!(),
-            SubFrameType::LinearPredictorOrder30 => todo!(),
-            SubFrameType::LinearPredictorOrder31 => todo!(),
-            SubFrameType::LinearPredictorOrder32 => todo!(),
+            // `check_sub_frame` lets no other types through.
+            _ => self.write_fixed(self.header.fixed_order().unwrap_or(0), bit_sink),
         }
     }
 
     /// TODO:
     ///     - partitions
-    fn wirte_predictor<const ORDER: usize>(
-        &self,
-        method: ResidualCodingMethod,
-        params: &RiceParams,
-        bit_sink: &mut impl BitSink,
-        predictor: impl Fn(&[i32], usize) -> i32,
-    ) {
+    fn write_fixed(&self, order: usize, bit_sink: &mut impl BitSink) {
+        // `new_checked` fell back to verbatim if they do not fit.
+        #[allow(clippy::cast_possible_truncation)]
+        let residuals = || {
+            fixed_residuals(self.samples, order, self.wasted_bits).map(|residual| residual as i32)
+        };
+        self.samples[..order].iter().for_each(|&sample| {
+            write_sample(sample.into(), bit_sink, self.bit_depth, self.wasted_bits);
+        });
+        let (method, params) = RiceParams::for_residuals(residuals());
         bit_sink.write(method as u8, 2);
         let partition_order = 0;
         bit_sink.write(partition_order, 4);
         params.write(bit_sink, method);
-        self.samples[..ORDER]
-            .iter()
-            .for_each(|&byte| write_sample(byte, bit_sink, self.bit_depth, self.wasted_bits));
-        let mut bufer = [0; ORDER];
-        for (slot, &value) in bufer.iter_mut().zip(self.samples[ORDER..].iter()) {
-            *slot = value;
-        }
-        self.samples[ORDER..]
-            .iter()
-            .copied()
-            .predict(bufer, predictor)
-            .for_each(|error| residual_codeing(params, bit_sink, error));
+        residuals().for_each(|error| residual_codeing(&params, bit_sink, error));
     }
 }
 
-#[derive(Clone, Copy)]
+/// Checks that a subframe of type `header` can be written at `bit_depth`
+/// from the samples alone.
+pub(super) fn check_sub_frame(
+    header: SubFrameType,
+    wasted_bits: u8,
+    bit_depth: u8,
+) -> Result<(), Error> {
+    if bit_depth > 32 || wasted_bits >= bit_depth {
+        return Err(Error::InvalidBitDepth);
+    }
+    match header {
+        SubFrameType::Constant | SubFrameType::Verbatim => Ok(()),
+        _ if header.fixed_order().is_some() => Ok(()),
+        _ => Err(Error::UnsupportedSubFrameType),
+    }
+}
+
+/// Residuals of the fixed predictor of order `order`, computed in 64 bits
+/// since they can outgrow the samples.
+fn fixed_residuals<S: Copy + Into<i32>>(
+    samples: &[S],
+    order: usize,
+    wasted_bits: u8,
+) -> impl Iterator<Item = i64> + '_ {
+    samples.windows(order + 1).map(move |window| {
+        let sample = |i: usize| i64::from(window[i].into());
+        let prediction: i64 = FIXED_COEFFICIENTS[order]
+            .iter()
+            .enumerate()
+            .map(|(j, &coefficient)| coefficient * sample(order - 1 - j))
+            .sum();
+        (sample(order) - prediction) >> wasted_bits
+    })
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub enum SubFrameType {
     Constant = 0b000_000,
     Verbatim = 0b000_001,
@@ -178,7 +238,25 @@ pub enum SubFrameType {
     LinearPredictorOrder32 = 0b111_111,
 }
 
-fn write_sample(sample: i32, bit_sink: &mut impl BitSink, bit_depth: u8, wasted_bits: u8) {
+impl SubFrameType {
+    fn fixed_order(self) -> Option<usize> {
+        match self {
+            SubFrameType::FixedPredictorOrder0 => Some(0),
+            SubFrameType::FixedPredictorOrder1 => Some(1),
+            SubFrameType::FixedPredictorOrder2 => Some(2),
+            SubFrameType::FixedPredictorOrder3 => Some(3),
+            SubFrameType::FixedPredictorOrder4 => Some(4),
+            _ => None,
+        }
+    }
+}
+
+pub(crate) fn write_sample(
+    sample: i32,
+    bit_sink: &mut impl BitSink,
+    bit_depth: u8,
+    wasted_bits: u8,
+) {
     let wasted_sample = sample >> wasted_bits;
     let used_bits = bit_depth - wasted_bits;
     let full_bytes = usize::from(used_bits) / 8;
@@ -197,89 +275,58 @@ fn write_sample(sample: i32, bit_sink: &mut impl BitSink, bit_depth: u8, wasted_
     });
 }
 
-struct Predictor<
-    const N: usize,
-    I: Iterator<Item = S>,
-    S: Sub<Output = S> + Copy,
-    F: Fn(&[I::Item], usize) -> I::Item,
-> {
-    iter: I,
-    prev: [I::Item; N],
-    last: usize,
-    predication_fn: F,
-}
-
-impl<
-        const N: usize,
-        I: Iterator<Item = S>,
-        S: Sub<Output = S> + Copy,
-        F: Fn(&[I::Item], usize) -> I::Item,
-    > Predictor<N, I, I::Item, F>
-{
-    fn new(iter: I, prev: [I::Item; N], predictor: F) -> Self {
-        Self {
-            iter,
-            prev,
-            last: N - 1,
-            predication_fn: predictor,
-        }
-    }
-}
-
-impl<
-        const N: usize,
-        I: Iterator<Item = S>,
-        S: Sub<Output = S> + Copy,
-        F: Fn(&[I::Item], usize) -> I::Item,
-    > Iterator for Predictor<N, I, I::Item, F>
-{
-    type Item = I::Item;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        let actual = self.iter.next()?;
-        let prediction = (self.predication_fn)(&self.prev, self.last);
-        self.prev[self.last] = prediction;
-        self.last += 1;
-        self.last %= self.prev.len();
-        Some(actual - prediction)
-    }
-}
-
-trait PredictorIter<
-    const N: usize,
-    F: Fn(&[Self::Item], usize) -> Self::Item,
-    S: Sub<Output = S> + Copy,
->: Iterator<Item = S>
-{
-    fn predict(self, warm_up: [Self::Item; N], predictor: F) -> Predictor<N, Self, Self::Item, F>
-    where
-        Self: Sized,
-    {
-        Predictor::new(self, warm_up, predictor)
-    }
-}
-
-impl<
-        I: Iterator<Item = S>,
-        S: Sub<Output = S> + Copy,
-        const N: usize,
-        F: Fn(&[I::Item], usize) -> Self::Item,
-    > PredictorIter<N, F, S> for I
-{
-}
-
 #[derive(Clone, Copy)]
-enum ResidualCodingMethod {
+pub(crate) enum ResidualCodingMethod {
     Rice4Bits = 0b00,
     Rice5Bits = 0b01,
 }
 
-enum RiceParams {
+#[derive(Clone, Copy)]
+pub(crate) enum RiceParams {
     Param(u8),
     Escape(u8),
 }
 
 impl RiceParams {
+    fn for_residuals(residuals: impl Iterator<Item = i32>) -> (ResidualCodingMethod, Self) {
+        let (count, sum, escape_bits) =
+            residuals.fold((0_u64, 0_u64, 0_u8), |(count, sum, bits), residual| {
+                (
+                    count + 1,
+                    sum + signed_fold(residual),
+                    bits.max(signed_bits(residual)),
+                )
+            });
+        let (params, _) = Self::for_partition(count, sum, escape_bits);
+        (params.method(), params)
+    }
+
+    /// Picks the cheapest parameter for a partition of `count` residuals and
+    /// returns it with the partition's size in bits, parameter field excluded.
+    ///
+    /// The Rice cost is estimated from the sum of the folded residuals, and
+    /// `escape_bits` is the widest residual in two's complement.
+    pub(crate) fn for_partition(count: u64, sum: u64, escape_bits: u8) -> (Self, u64) {
+        let (rice, rice_cost) = (0..=30)
+            .map(|rice| (rice, count * (u64::from(rice) + 1) + (sum >> rice)))
+            .min_by_key(|&(_, cost)| cost)
+            .unwrap_or((0, 0));
+        let escape_cost = count * u64::from(escape_bits) + 5;
+        if escape_bits < 32 && escape_cost < rice_cost {
+            (RiceParams::Escape(escape_bits), escape_cost)
+        } else {
+            (RiceParams::Param(rice), rice_cost)
+        }
+    }
+
+    /// Parameters above 14 only fit the 5-bit coding method.
+    pub(crate) fn method(self) -> ResidualCodingMethod {
+        match self {
+            RiceParams::Param(rice) if rice > 14 => ResidualCodingMethod::Rice5Bits,
+            _ => ResidualCodingMethod::Rice4Bits,
+        }
+    }
+
     pub fn write(&self, bit_sink: &mut impl BitSink, method: ResidualCodingMethod) {
         let bits = match method {
             ResidualCodingMethod::Rice4Bits => 4,
@@ -287,18 +334,34 @@ impl RiceParams {
         };
         match self {
             RiceParams::Param(par) => bit_sink.write(*par, bits),
-            RiceParams::Escape(_) => bit_sink.write(0xFF, bits),
+            RiceParams::Escape(num_bits) => {
+                bit_sink.write(0xFF, bits);
+                bit_sink.write(*num_bits, 5);
+            }
         }
     }
 }
 
+/// Bits needed to store `n` in two's complement.
+#[allow(clippy::cast_possible_truncation)]
+pub(crate) fn signed_bits(n: i32) -> u8 {
+    if n == 0 {
+        0
+    } else {
+        (i32::BITS - (n ^ (n >> 31)).leading_zeros() + 1) as u8
+    }
+}
+
+/// Folds `n` onto the unsigned numbers for Rice coding, which takes 33 bits
+/// for the extremes of `i32`.
 #[allow(clippy::cast_sign_loss)]
-fn signed_fold(n: i32) -> u32 {
-    (if n.is_negative() { n * (-2) - 1 } else { n * 2 }) as u32
+pub(crate) fn signed_fold(n: i32) -> u64 {
+    let n = i64::from(n);
+    (if n.is_negative() { n * (-2) - 1 } else { n * 2 }) as u64
 }
 
 #[allow(clippy::cast_possible_truncation)]
-fn unary_code(code: u32, bit_sink: &mut impl BitSink) {
+pub(crate) fn unary_code(code: u64, bit_sink: &mut impl BitSink) {
     let mut remaining = code;
     while remaining > 0 {
         let push = min(remaining, 8);
@@ -309,15 +372,18 @@ fn unary_code(code: u32, bit_sink: &mut impl BitSink) {
 }
 
 #[allow(clippy::cast_possible_wrap)]
-fn rice_code(lsp_size: u8, sample: u32, bit_sink: &mut impl BitSink) {
-    let shift = i32::BITS - u32::from(lsp_size);
-    let lsp = (sample << shift) >> shift;
+#[allow(clippy::cast_possible_truncation)]
+fn rice_code(lsp_size: u8, sample: u64, bit_sink: &mut impl BitSink) {
+    let lsp = sample
+        & u64::MAX
+            .checked_shr(u64::BITS - u32::from(lsp_size))
+            .unwrap_or(0);
     let msp = sample >> lsp_size;
     unary_code(msp, bit_sink);
     write_sample(lsp as i32, bit_sink, lsp_size, 0);
 }
 
-fn residual_codeing(params: &RiceParams, bit_sink: &mut impl BitSink, sample: i32) {
+pub(crate) fn residual_codeing(params: &RiceParams, bit_sink: &mut impl BitSink, sample: i32) {
     match params {
         RiceParams::Param(rice) => rice_code(*rice, signed_fold(sample), bit_sink),
         RiceParams::Escape(num_bits) => write_sample(sample, bit_sink, *num_bits, 0),
@@ -2,6 +2,88 @@
 
 pub trait ByteSink {
     fn write(&mut self, next_byte: u8);
+
+    /// Writes `bytes` in order; sinks that can copy a whole slice at once
+    /// should override it.
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        bytes.iter().for_each(|&byte| self.write(byte));
+    }
+
+    /// Writes `byte` `count` times.
+    fn write_repeated(&mut self, byte: u8, count: usize) {
+        (0..count).for_each(|_| self.write(byte));
+    }
+}
+
+impl<S: ByteSink + ?Sized> ByteSink for &mut S {
+    fn write(&mut self, next_byte: u8) {
+        (**self).write(next_byte);
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        (**self).write_bytes(bytes);
+    }
+
+    fn write_repeated(&mut self, byte: u8, count: usize) {
+        (**self).write_repeated(byte, count);
+    }
+}
+
+/// Where encoded data is read back from.
+pub trait ByteSource {
+    /// The next byte, or `None` at the end of the data.
+    fn read(&mut self) -> Option<u8>;
+
+    /// Fills `bytes`, returning `false` if the data ended first.
+    fn read_bytes(&mut self, bytes: &mut [u8]) -> bool {
+        for byte in bytes {
+            match self.read() {
+                Some(next_byte) => *byte = next_byte,
+                None => return false,
+            }
+        }
+        true
+    }
+
+    /// Skips `count` bytes, returning `false` if the data ended first.
+    fn skip(&mut self, count: usize) -> bool {
+        (0..count).all(|_| self.read().is_some())
+    }
+}
+
+/// Storage that can be read, overwritten and appended to at any position,
+/// like a file. Reads and writes share the [`SeekableByteSink`] position.
+pub trait ByteStorage: ByteSource + SeekableByteSink {
+    /// Bytes stored.
+    fn len(&self) -> u64;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<S: ByteSource + ?Sized> ByteSource for &mut S {
+    fn read(&mut self) -> Option<u8> {
+        (**self).read()
+    }
+
+    fn read_bytes(&mut self, bytes: &mut [u8]) -> bool {
+        (**self).read_bytes(bytes)
+    }
+
+    fn skip(&mut self, count: usize) -> bool {
+        (**self).skip(count)
+    }
+}
+
+/// A [`ByteSink`] that can go back and overwrite what it already holds.
+///
+/// Writing after a [`seek`](Self::seek) overwrites the bytes from that
+/// position on; it does not insert.
+pub trait SeekableByteSink: ByteSink {
+    /// Position the next byte will be written at.
+    fn position(&self) -> u64;
+    fn seek(&mut self, position: u64);
 }
 
 /// Appends the next `num_bits` from `next_bits` to the sink.
@@ -16,6 +98,7 @@ pub trait BitSink {
 
 pub struct BufferByteSink<const N: usize> {
     length: usize,
+    position: usize,
     buff: [u8; N],
 }
 
@@ -23,6 +106,7 @@ impl<const N: usize> BufferByteSink<N> {
     pub fn new() -> Self {
         Self {
             length: 0,
+            position: 0,
             buff: [0; N],
         }
     }
@@ -33,13 +117,189 @@ impl<const N: usize> BufferByteSink<N> {
 
     pub fn del_last(&mut self) {
         self.length -= 1;
+        self.position = self.position.min(self.length);
+    }
+}
+
+impl<const N: usize> Default for BufferByteSink<N> {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl<const N: usize> ByteSink for BufferByteSink<N> {
     fn write(&mut self, next_byte: u8) {
-        self.buff[self.length] = next_byte;
-        self.length += 1;
+        self.buff[self.position] = next_byte;
+        self.position += 1;
+        self.length = self.length.max(self.position);
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        let end = self.position + bytes.len();
+        self.buff[self.position..end].copy_from_slice(bytes);
+        self.position = end;
+        self.length = self.length.max(self.position);
+    }
+
+    fn write_repeated(&mut self, byte: u8, count: usize) {
+        let end = self.position + count;
+        self.buff[self.position..end].fill(byte);
+        self.position = end;
+        self.length = self.length.max(self.position);
+    }
+}
+
+impl<const N: usize> SeekableByteSink for BufferByteSink<N> {
+    fn position(&self) -> u64 {
+        self.position as u64
+    }
+
+    /// Panics past the end of what was written.
+    #[allow(clippy::cast_possible_truncation)]
+    fn seek(&mut self, position: u64) {
+        assert!(position <= self.length as u64);
+        self.position = position as usize;
+    }
+}
+
+impl<const N: usize> ByteSource for BufferByteSink<N> {
+    fn read(&mut self) -> Option<u8> {
+        let byte = *self.as_slice().get(self.position)?;
+        self.position += 1;
+        Some(byte)
+    }
+}
+
+impl<const N: usize> ByteStorage for BufferByteSink<N> {
+    fn len(&self) -> u64 {
+        self.length as u64
+    }
+}
+
+/// Writes into a borrowed slice, remembering instead of panicking when the
+/// slice runs out.
+pub struct SliceByteSink<'a> {
+    length: usize,
+    position: usize,
+    overflowed: bool,
+    buff: &'a mut [u8],
+}
+
+impl<'a> SliceByteSink<'a> {
+    pub fn new(buff: &'a mut [u8]) -> Self {
+        Self {
+            length: 0,
+            position: 0,
+            overflowed: false,
+            buff,
+        }
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.buff[0..self.length]
+    }
+
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    /// Whether a byte was dropped because the slice was full.
+    pub fn overflowed(&self) -> bool {
+        self.overflowed
+    }
+}
+
+impl ByteSink for SliceByteSink<'_> {
+    fn write(&mut self, next_byte: u8) {
+        if let Some(slot) = self.buff.get_mut(self.position) {
+            *slot = next_byte;
+            self.position += 1;
+            self.length = self.length.max(self.position);
+        } else {
+            self.overflowed = true;
+        }
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        let free = self.buff.len() - self.position;
+        let written = bytes.len().min(free);
+        self.buff[self.position..self.position + written].copy_from_slice(&bytes[..written]);
+        self.position += written;
+        self.length = self.length.max(self.position);
+        self.overflowed |= written < bytes.len();
+    }
+
+    fn write_repeated(&mut self, byte: u8, count: usize) {
+        let free = self.buff.len() - self.position;
+        let written = count.min(free);
+        self.buff[self.position..self.position + written].fill(byte);
+        self.position += written;
+        self.length = self.length.max(self.position);
+        self.overflowed |= written < count;
+    }
+}
+
+impl SeekableByteSink for SliceByteSink<'_> {
+    fn position(&self) -> u64 {
+        self.position as u64
+    }
+
+    /// Panics past the end of what was written.
+    #[allow(clippy::cast_possible_truncation)]
+    fn seek(&mut self, position: u64) {
+        assert!(position <= self.length as u64);
+        self.position = position as usize;
+    }
+}
+
+/// Reads a borrowed slice front to back.
+pub struct SliceByteSource<'a> {
+    position: usize,
+    buff: &'a [u8],
+}
+
+impl<'a> SliceByteSource<'a> {
+    pub fn new(buff: &'a [u8]) -> Self {
+        Self { position: 0, buff }
+    }
+
+    /// Bytes read or skipped so far.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// What is left to read.
+    pub fn remaining(&self) -> &'a [u8] {
+        &self.buff[self.position..]
+    }
+}
+
+impl ByteSource for SliceByteSource<'_> {
+    fn read(&mut self) -> Option<u8> {
+        let byte = *self.buff.get(self.position)?;
+        self.position += 1;
+        Some(byte)
+    }
+
+    fn read_bytes(&mut self, bytes: &mut [u8]) -> bool {
+        let Some(next_bytes) = self.remaining().get(..bytes.len()) else {
+            return false;
+        };
+        bytes.copy_from_slice(next_bytes);
+        self.position += bytes.len();
+        true
+    }
+
+    fn skip(&mut self, count: usize) -> bool {
+        if self.remaining().len() < count {
+            return false;
+        }
+        self.position += count;
+        true
     }
 }
 
@@ -72,6 +332,10 @@ impl<BS: ByteSink> BitSink for BitSinkAdapter<'_, BS> {
     #[allow(clippy::cast_sign_loss)]
     fn write(&mut self, next_bits: u8, num_bits: u8) {
         assert!(num_bits <= 8);
+        if num_bits == 0 {
+            return;
+        }
+        let next_bits = next_bits & (u8::MAX >> (8 - num_bits));
         let shift = 8 - i32::from(num_bits) - i32::from(self.bits);
         if shift.is_negative() {
             let shift = -shift;
@@ -92,6 +356,14 @@ impl<BS: ByteSink> BitSink for BitSinkAdapter<'_, BS> {
     }
 }
 
+pub mod budget;
+pub mod container;
+pub mod decoder;
+pub mod encoder;
+mod error;
 pub mod frames;
 pub mod metadata;
 mod utils;
+
+pub use error::Error;
+pub use utils::{BitSource, SliceBitSource};
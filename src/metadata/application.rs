@@ -1,65 +1,222 @@
-use crate::ByteSink;
+use crate::{ByteSink, Error};
 
-pub struct Application<const N: usize> {
+#[derive(Clone, Copy)]
+pub struct Application<'a> {
     app_id: AppId,
-    pub app_data: [u8; N],
+    data: ApplicationData<'a>,
 }
 
-#[repr(u32)]
-enum AppId {
-    FlacFile = 0x4154_4348,
-    BeSolo = 0x4253_4F4C,
-    BugsPlayer = 0x4255_4753,
-    GoldWaveCuePoints = 0x4375_6573,
-    CueSplitter = 0x4669_6361,
-    FacTools = 0x4674_6F6C,
-    MotbMetaCzar = 0x4D4F_5442,
-    Mp3StreamEditor = 0x4D50_5345,
-    MusicMlMusicMetadataLanguage = 0x4D75_4D4C,
-    SoundDevicesRiffChunkStorage = 0x5249_4646,
-    SoundFontFlac = 0x5346_464C,
-    SonyCreativeSoftware = 0x534F_4E59,
-    Facsqueeze = 0x5351_455A,
-    TwistedWave = 0x5474_5776,
-    UitsEmbeddingTools = 0x5549_5453,
-    FlacAiffChunkStorage = 0x6169_6666,
-    FacImage = 0x696D_6167,
-    ParseableEmbeddedExtensibleMetadata = 0x7065_656D,
-    QflacStudio = 0x7166_7374,
-    FlacRiffChunkStorage = 0x7269_6666,
-    TagTuner = 0x7475_6E65,
-    FlacWave64ChunkStorage = 0x7736_3420,
-    XBAT = 0x7862_6174,
-    Xmcd = 0x786D_6364,
+/// Application ID, one of the IDs registered with the FLAC project or any
+/// other four-character code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AppId {
+    FlacFile,
+    BeSolo,
+    BugsPlayer,
+    GoldWaveCuePoints,
+    CueSplitter,
+    FacTools,
+    MotbMetaCzar,
+    Mp3StreamEditor,
+    MusicMlMusicMetadataLanguage,
+    SoundDevicesRiffChunkStorage,
+    SoundFontFlac,
+    SonyCreativeSoftware,
+    Facsqueeze,
+    TwistedWave,
+    UitsEmbeddingTools,
+    FlacAiffChunkStorage,
+    FacImage,
+    ParseableEmbeddedExtensibleMetadata,
+    QflacStudio,
+    FlacRiffChunkStorage,
+    TagTuner,
+    FlacWave64ChunkStorage,
+    Xbat,
+    Xmcd,
     Other(u32),
 }
 
 impl AppId {
-    fn id(&self) -> u32 {
+    const REGISTERED: [AppId; 24] = [
+        AppId::FlacFile,
+        AppId::BeSolo,
+        AppId::BugsPlayer,
+        AppId::GoldWaveCuePoints,
+        AppId::CueSplitter,
+        AppId::FacTools,
+        AppId::MotbMetaCzar,
+        AppId::Mp3StreamEditor,
+        AppId::MusicMlMusicMetadataLanguage,
+        AppId::SoundDevicesRiffChunkStorage,
+        AppId::SoundFontFlac,
+        AppId::SonyCreativeSoftware,
+        AppId::Facsqueeze,
+        AppId::TwistedWave,
+        AppId::UitsEmbeddingTools,
+        AppId::FlacAiffChunkStorage,
+        AppId::FacImage,
+        AppId::ParseableEmbeddedExtensibleMetadata,
+        AppId::QflacStudio,
+        AppId::FlacRiffChunkStorage,
+        AppId::TagTuner,
+        AppId::FlacWave64ChunkStorage,
+        AppId::Xbat,
+        AppId::Xmcd,
+    ];
+
+    /// The registered variant for `id`, [`AppId::Other`] for anything else.
+    pub fn from_id(id: u32) -> Self {
+        Self::REGISTERED
+            .into_iter()
+            .find(|app_id| app_id.id() == id)
+            .unwrap_or(Self::Other(id))
+    }
+
+    /// `AppId::from_code(*b"ATCH")` is [`AppId::FlacFile`].
+    pub fn from_code(code: [u8; 4]) -> Self {
+        Self::from_id(u32::from_be_bytes(code))
+    }
+
+    pub const fn id(&self) -> u32 {
         match self {
-            Self::Other(n) => *n,
-            _ => unsafe { *<*const _>::from(self).cast::<u32>() },
+            Self::FlacFile => 0x4154_4348,
+            Self::BeSolo => 0x4253_4F4C,
+            Self::BugsPlayer => 0x4255_4753,
+            Self::GoldWaveCuePoints => 0x4375_6573,
+            Self::CueSplitter => 0x4669_6361,
+            Self::FacTools => 0x4674_6F6C,
+            Self::MotbMetaCzar => 0x4D4F_5442,
+            Self::Mp3StreamEditor => 0x4D50_5345,
+            Self::MusicMlMusicMetadataLanguage => 0x4D75_4D4C,
+            Self::SoundDevicesRiffChunkStorage => 0x5249_4646,
+            Self::SoundFontFlac => 0x5346_464C,
+            Self::SonyCreativeSoftware => 0x534F_4E59,
+            Self::Facsqueeze => 0x5351_455A,
+            Self::TwistedWave => 0x5474_5776,
+            Self::UitsEmbeddingTools => 0x5549_5453,
+            Self::FlacAiffChunkStorage => 0x6169_6666,
+            Self::FacImage => 0x696D_6167,
+            Self::ParseableEmbeddedExtensibleMetadata => 0x7065_656D,
+            Self::QflacStudio => 0x7166_7374,
+            Self::FlacRiffChunkStorage => 0x7269_6666,
+            Self::TagTuner => 0x7475_6E65,
+            Self::FlacWave64ChunkStorage => 0x7736_3420,
+            Self::Xbat => 0x7862_6174,
+            Self::Xmcd => 0x786D_6364,
+            Self::Other(id) => *id,
         }
     }
+
+    pub const fn code(&self) -> [u8; 4] {
+        self.id().to_be_bytes()
+    }
+}
+
+/// A typed APPLICATION payload that serializes itself.
+pub trait ApplicationPayload {
+    fn app_id(&self) -> AppId;
+    /// Bytes [`write`](Self::write) produces, the application ID excluded.
+    fn len(&self) -> usize;
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    fn write(&self, sink: &mut dyn ByteSink);
+}
+
+#[derive(Clone, Copy)]
+enum ApplicationData<'a> {
+    Bytes(&'a [u8]),
+    Payload(&'a dyn ApplicationPayload),
 }
 
-impl<const N: usize> Application<N> {
-    pub fn new(app_id: AppId) -> Self {
+impl<'a> Application<'a> {
+    pub fn new(app_id: AppId, app_data: &'a [u8]) -> Self {
         Self {
             app_id,
-            app_data: [0; N],
+            data: ApplicationData::Bytes(app_data),
+        }
+    }
+
+    /// Parses an APPLICATION payload.
+    pub fn from_bytes(payload: &'a [u8]) -> Result<Self, Error> {
+        match payload {
+            [a, b, c, d, app_data @ ..] => {
+                Ok(Self::new(AppId::from_code([*a, *b, *c, *d]), app_data))
+            }
+            _ => Err(Error::InvalidMetadata),
+        }
+    }
+
+    pub fn from_payload(payload: &'a dyn ApplicationPayload) -> Self {
+        Self {
+            app_id: payload.app_id(),
+            data: ApplicationData::Payload(payload),
         }
     }
 
+    pub fn app_id(&self) -> AppId {
+        self.app_id
+    }
+
+    /// Payload length: the 4-byte id followed by the data.
+    pub fn len(&self) -> usize {
+        4 + match self.data {
+            ApplicationData::Bytes(bytes) => bytes.len(),
+            ApplicationData::Payload(payload) => payload.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
     pub fn write<BS: ByteSink>(&self, sink: &mut BS) {
-        for val in self
-            .app_id
-            .id()
-            .to_be_bytes()
-            .into_iter()
-            .chain(self.app_data.into_iter())
-        {
-            sink.write(val);
+        sink.write_bytes(&self.app_id.code());
+        match self.data {
+            ApplicationData::Bytes(bytes) => sink.write_bytes(bytes),
+            ApplicationData::Payload(payload) => payload.write(sink),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AppId, Application, ApplicationPayload};
+    use crate::{BufferByteSink, ByteSink};
+
+    struct Telemetry {
+        temperature: i16,
+        battery: u8,
+    }
+
+    impl ApplicationPayload for Telemetry {
+        fn app_id(&self) -> AppId {
+            AppId::from_code(*b"TLMY")
         }
+        fn len(&self) -> usize {
+            3
+        }
+        fn write(&self, sink: &mut dyn ByteSink) {
+            sink.write_bytes(&self.temperature.to_be_bytes());
+            sink.write(self.battery);
+        }
+    }
+
+    #[test]
+    fn ids_and_payloads() {
+        assert_eq!(AppId::from_code(*b"xbat"), AppId::Xbat);
+        assert_eq!(AppId::from_id(0x7862_6174), AppId::Xbat);
+        assert_eq!(AppId::Xmcd.code(), *b"xmcd");
+
+        let telemetry = Telemetry {
+            temperature: -40,
+            battery: 87,
+        };
+        let application = Application::from_payload(&telemetry);
+        let mut sink: BufferByteSink<8> = BufferByteSink::new();
+        application.write(&mut sink);
+        assert_eq!(application.len(), sink.as_slice().len());
+        assert_eq!(sink.as_slice(), b"TLMY\xff\xd8\x57");
     }
 }
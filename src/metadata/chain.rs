@@ -0,0 +1,149 @@
+use super::{MetaDataBlockType, FLAC_MARKER};
+use crate::{ByteSink, Error};
+
+/// Collects up to `CAPACITY` metadata blocks and writes them as the start of
+/// a native FLAC stream.
+///
+/// STREAMINFO is written first whatever the order the blocks were pushed in,
+/// and only the block written last gets the last-block flag.
+pub struct MetadataChain<'a, const CAPACITY: usize> {
+    length: usize,
+    blocks: [Option<MetaDataBlockType<'a>>; CAPACITY],
+}
+
+impl<'a, const CAPACITY: usize> MetadataChain<'a, CAPACITY> {
+    pub fn new() -> Self {
+        Self {
+            length: 0,
+            blocks: [const { None }; CAPACITY],
+        }
+    }
+
+    /// Fails if the chain is full or if `block` is a second STREAMINFO or
+    /// SEEKTABLE.
+    pub fn push(&mut self, block: MetaDataBlockType<'a>) -> Result<&mut Self, Error> {
+        match block {
+            MetaDataBlockType::StreamInfo(_) if self.stream_info().is_some() => {
+                return Err(Error::DuplicateStreamInfo);
+            }
+            MetaDataBlockType::SeekTable(_)
+                if self
+                    .blocks()
+                    .any(|block| matches!(block, MetaDataBlockType::SeekTable(_))) =>
+            {
+                return Err(Error::DuplicateSeekTable);
+            }
+            _ => {}
+        }
+        let slot = self
+            .blocks
+            .get_mut(self.length)
+            .ok_or(Error::TooManyBlocks)?;
+        *slot = Some(block);
+        self.length += 1;
+        Ok(self)
+    }
+
+    /// Writes the `fLaC` marker followed by every block.
+    ///
+    /// Nothing is written if a block is too large for its length field.
+    pub fn write<BS: ByteSink>(&self, sink: &mut BS) -> Result<(), Error> {
+        let stream_info = self.check()?;
+        sink.write_bytes(&FLAC_MARKER);
+        let mut others = self
+            .blocks()
+            .filter(|block| !matches!(block, MetaDataBlockType::StreamInfo(_)))
+            .peekable();
+        stream_info.write_block(others.peek().is_none(), sink)?;
+        while let Some(block) = others.next() {
+            block.write_block(others.peek().is_none(), sink)?;
+        }
+        Ok(())
+    }
+
+    /// Bytes [`write`](Self::write) produces, marker included.
+    pub fn len(&self) -> usize {
+        FLAC_MARKER.len() + self.blocks().map(|block| 4 + block.len()).sum::<usize>()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    /// The blocks in the order they were pushed.
+    pub fn blocks(&self) -> impl Iterator<Item = &MetaDataBlockType<'a>> {
+        self.blocks[..self.length].iter().flatten()
+    }
+
+    /// Keeps only the blocks `keep` returns `true` for, in the same order.
+    pub fn retain(&mut self, mut keep: impl FnMut(&MetaDataBlockType<'a>) -> bool) {
+        let mut length = 0;
+        for i in 0..self.length {
+            if self.blocks[i].as_ref().is_some_and(&mut keep) {
+                self.blocks.swap(length, i);
+                length += 1;
+            }
+        }
+        self.blocks[length..self.length].fill_with(|| None);
+        self.length = length;
+    }
+
+    /// Fails the way [`write`](Self::write) would, without writing anything,
+    /// and returns the STREAMINFO block otherwise.
+    pub(crate) fn check(&self) -> Result<&MetaDataBlockType<'a>, Error> {
+        let stream_info = self.stream_info().ok_or(Error::MissingStreamInfo)?;
+        for block in self.blocks() {
+            block.check_len()?;
+        }
+        Ok(stream_info)
+    }
+
+    fn stream_info(&self) -> Option<&MetaDataBlockType<'a>> {
+        self.blocks()
+            .find(|block| matches!(block, MetaDataBlockType::StreamInfo(_)))
+    }
+}
+
+impl<const CAPACITY: usize> Default for MetadataChain<'_, CAPACITY> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MetadataChain;
+    use crate::{
+        metadata::{padding::Padding, stream_info::StreamInfo, MetaDataBlockType},
+        BufferByteSink, Error,
+    };
+
+    #[test]
+    fn stream_info_first_and_last_flag() {
+        let mut chain: MetadataChain<3> = MetadataChain::new();
+        assert_eq!(
+            chain.write(&mut BufferByteSink::<64>::new()),
+            Err(Error::MissingStreamInfo)
+        );
+        chain
+            .push(MetaDataBlockType::Padding(Padding::new(4)))
+            .unwrap()
+            .push(MetaDataBlockType::StreamInfo(StreamInfo::new(
+                44_100, 2, 16,
+            )))
+            .unwrap();
+        assert!(matches!(
+            chain.push(MetaDataBlockType::StreamInfo(StreamInfo::new(
+                44_100, 2, 16
+            ))),
+            Err(Error::DuplicateStreamInfo)
+        ));
+
+        let mut sink: BufferByteSink<64> = BufferByteSink::new();
+        chain.write(&mut sink).unwrap();
+        let bytes = sink.as_slice();
+        assert_eq!(bytes.len(), chain.len());
+        assert_eq!(&bytes[..8], b"fLaC\x00\x00\x00\x22");
+        assert_eq!(&bytes[4 + 4 + 34..], &[0b1000_0001, 0, 0, 4, 0, 0, 0, 0]);
+    }
+}
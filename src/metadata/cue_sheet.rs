@@ -0,0 +1,423 @@
+use super::stream_info::StreamInfo;
+use crate::{utils::read_be, ByteSink, Error};
+
+/// Samples in one CD sector at 44.1 kHz; CD-DA offsets are multiples of it.
+pub const CD_SECTOR_SAMPLES: u64 = 588;
+
+const CATALOG_LENGTH: usize = 128;
+const ISRC_LENGTH: usize = 12;
+const HEADER_LENGTH: usize = CATALOG_LENGTH + 8 + 259 + 1;
+const TRACK_LENGTH: usize = 8 + 1 + ISRC_LENGTH + 14 + 1;
+const INDEX_LENGTH: usize = 8 + 1 + 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CueSheetIndex {
+    /// Samples from the start of the track.
+    pub offset: u64,
+    pub number: u8,
+}
+
+/// The index points of a track, given as a slice or read back from a
+/// payload.
+#[derive(Debug, Clone, Copy)]
+pub struct CueSheetIndices<'a> {
+    indices: &'a [CueSheetIndex],
+    raw: &'a [u8],
+}
+
+impl<'a> CueSheetIndices<'a> {
+    pub fn len(&self) -> usize {
+        self.indices.len() + self.raw.len() / INDEX_LENGTH
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn iter(&self) -> impl Iterator<Item = CueSheetIndex> + 'a {
+        self.indices
+            .iter()
+            .copied()
+            .chain(
+                self.raw
+                    .chunks_exact(INDEX_LENGTH)
+                    .map(|index| CueSheetIndex {
+                        offset: read_be(index, 0, 8).unwrap_or_default(),
+                        number: index[8],
+                    }),
+            )
+    }
+}
+
+impl PartialEq for CueSheetIndices<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.iter().eq(other.iter())
+    }
+}
+
+impl Eq for CueSheetIndices<'_> {}
+
+impl<'a> From<&'a [CueSheetIndex]> for CueSheetIndices<'a> {
+    fn from(indices: &'a [CueSheetIndex]) -> Self {
+        Self { indices, raw: &[] }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CueSheetTrack<'a> {
+    /// Samples from the start of the stream.
+    pub offset: u64,
+    pub number: u8,
+    /// Empty, or the 12 characters of the ISRC.
+    pub isrc: &'a str,
+    pub is_audio: bool,
+    pub pre_emphasis: bool,
+    pub indices: CueSheetIndices<'a>,
+}
+
+impl<'a> CueSheetTrack<'a> {
+    /// An audio track without ISRC or pre-emphasis.
+    pub fn new(offset: u64, number: u8, indices: &'a [CueSheetIndex]) -> Self {
+        Self {
+            offset,
+            number,
+            isrc: "",
+            is_audio: true,
+            pre_emphasis: false,
+            indices: indices.into(),
+        }
+    }
+
+    /// Parses the track at the start of `bytes`, returning it and its length.
+    fn from_bytes(bytes: &'a [u8]) -> Option<(Self, usize)> {
+        let isrc = bytes.get(9..9 + ISRC_LENGTH)?;
+        let isrc_length = isrc
+            .iter()
+            .position(|&byte| byte == 0)
+            .unwrap_or(ISRC_LENGTH);
+        let flags = *bytes.get(9 + ISRC_LENGTH)?;
+        let index_count = usize::from(*bytes.get(TRACK_LENGTH - 1)?);
+        let length = TRACK_LENGTH + index_count * INDEX_LENGTH;
+        let track = Self {
+            offset: read_be(bytes, 0, 8)?,
+            number: bytes[8],
+            isrc: core::str::from_utf8(&isrc[..isrc_length]).ok()?,
+            is_audio: flags & 0b1000_0000 == 0,
+            pre_emphasis: flags & 0b0100_0000 != 0,
+            indices: CueSheetIndices {
+                indices: &[],
+                raw: bytes.get(TRACK_LENGTH..length)?,
+            },
+        };
+        Some((track, length))
+    }
+
+    fn len(&self) -> usize {
+        TRACK_LENGTH + INDEX_LENGTH * self.indices.len()
+    }
+
+    fn write<BS: ByteSink>(&self, sink: &mut BS) {
+        self.offset
+            .to_be_bytes()
+            .iter()
+            .chain(&[self.number])
+            .chain(self.isrc.as_bytes())
+            .chain(&[0; ISRC_LENGTH][self.isrc.len()..])
+            .for_each(|&byte| sink.write(byte));
+        let flags = (u8::from(!self.is_audio) << 7) | (u8::from(self.pre_emphasis) << 6);
+        sink.write(flags);
+        sink.write_repeated(0, 13);
+        #[allow(clippy::cast_possible_truncation)]
+        sink.write(self.indices.len() as u8);
+        for index in self.indices.iter() {
+            index
+                .offset
+                .to_be_bytes()
+                .iter()
+                .chain(&[index.number, 0, 0, 0])
+                .for_each(|&byte| sink.write(byte));
+        }
+    }
+}
+
+/// A CUESHEET block; the lead-out track is added after the tracks when
+/// written.
+#[derive(Clone, Copy)]
+pub struct CueSheet<'a> {
+    media_catalog_number: &'a str,
+    lead_in: u64,
+    is_cd: bool,
+    tracks: &'a [CueSheetTrack<'a>],
+    /// Tracks read back from a payload, lead-out excluded.
+    raw_tracks: &'a [u8],
+    lead_out: u64,
+}
+
+impl<'a> CueSheet<'a> {
+    /// Checks the sheet against the stream it describes.
+    ///
+    /// Track numbers must be unique and non-zero, tracks must be in order
+    /// and each needs index points numbered 0 or 1 and up. With `is_cd` the
+    /// stream must also be 44.1 kHz 16-bit stereo, tracks numbered 1 to 99,
+    /// and every offset a multiple of [`CD_SECTOR_SAMPLES`]; without it the
+    /// lead-in must be 0.
+    pub fn new(
+        media_catalog_number: &'a str,
+        lead_in: u64,
+        is_cd: bool,
+        tracks: &'a [CueSheetTrack<'a>],
+        lead_out: u64,
+        stream_info: &StreamInfo,
+    ) -> Result<Self, Error> {
+        let cue_sheet = Self {
+            media_catalog_number,
+            lead_in,
+            is_cd,
+            tracks,
+            raw_tracks: &[],
+            lead_out,
+        };
+        if cue_sheet.is_valid(stream_info) {
+            Ok(cue_sheet)
+        } else {
+            Err(Error::InvalidCueSheet)
+        }
+    }
+
+    /// Parses a CUESHEET payload. Only its layout is checked; see
+    /// [`is_valid`](Self::is_valid) for the rest.
+    pub fn from_bytes(payload: &'a [u8]) -> Result<Self, Error> {
+        let parse = || {
+            let catalog = &payload.get(..CATALOG_LENGTH)?;
+            let catalog_length = catalog
+                .iter()
+                .position(|&byte| byte == 0)
+                .unwrap_or(CATALOG_LENGTH);
+            let is_cd = *payload.get(CATALOG_LENGTH + 8)? & 0b1000_0000 != 0;
+            // The lead-out track is always there.
+            let track_count = *payload.get(HEADER_LENGTH - 1)?;
+            if track_count == 0 {
+                return None;
+            }
+            let mut position = HEADER_LENGTH;
+            for _ in 1..track_count {
+                position += CueSheetTrack::from_bytes(payload.get(position..)?)?.1;
+            }
+            let (lead_out, length) = CueSheetTrack::from_bytes(payload.get(position..)?)?;
+            let lead_out_number = if is_cd { 170 } else { 255 };
+            if lead_out.number != lead_out_number
+                || !lead_out.indices.is_empty()
+                || position + length != payload.len()
+            {
+                return None;
+            }
+            Some(Self {
+                media_catalog_number: core::str::from_utf8(&catalog[..catalog_length]).ok()?,
+                lead_in: read_be(payload, CATALOG_LENGTH, 8)?,
+                is_cd,
+                tracks: &[],
+                raw_tracks: &payload[HEADER_LENGTH..position],
+                lead_out: lead_out.offset,
+            })
+        };
+        parse().ok_or(Error::InvalidMetadata)
+    }
+
+    pub fn media_catalog_number(&self) -> &'a str {
+        self.media_catalog_number
+    }
+
+    pub fn lead_in(&self) -> u64 {
+        self.lead_in
+    }
+
+    pub fn is_cd(&self) -> bool {
+        self.is_cd
+    }
+
+    /// Offset of the lead-out track, in samples from the start of the
+    /// stream.
+    pub fn lead_out(&self) -> u64 {
+        self.lead_out
+    }
+
+    /// Every track but the lead-out.
+    pub fn tracks(&self) -> impl Iterator<Item = CueSheetTrack<'a>> + 'a {
+        let mut raw_tracks = self.raw_tracks;
+        self.tracks
+            .iter()
+            .copied()
+            .chain(core::iter::from_fn(move || {
+                let (track, length) = CueSheetTrack::from_bytes(raw_tracks)?;
+                raw_tracks = &raw_tracks[length..];
+                Some(track)
+            }))
+    }
+
+    /// Whether the sheet follows the rules [`new`](Self::new) checks.
+    pub fn is_valid(&self, stream_info: &StreamInfo) -> bool {
+        let printable = |text: &str| text.bytes().all(|byte| (0x20..=0x7E).contains(&byte));
+        let on_sector = |offset: u64| !self.is_cd || offset.is_multiple_of(CD_SECTOR_SAMPLES);
+        let max_track = if self.is_cd { 99 } else { 254 };
+        let header_valid = self.media_catalog_number.len() <= CATALOG_LENGTH
+            && printable(self.media_catalog_number)
+            && (!self.is_cd || self.tracks().count() < 100)
+            && (self.is_cd || self.lead_in == 0)
+            && on_sector(self.lead_out)
+            && self
+                .tracks()
+                .last()
+                .is_none_or(|track| track.offset <= self.lead_out);
+        let cd_valid = !self.is_cd
+            || (stream_info.sample_rate() == 44_100
+                && stream_info.bits_per_sample() == 16
+                && stream_info.channels() == 2);
+        let mut previous: Option<CueSheetTrack> = None;
+        let tracks_valid = self.tracks().all(|track| {
+            let isrc_valid = track.isrc.is_empty()
+                || (track.isrc.len() == ISRC_LENGTH
+                    && track.isrc.bytes().all(|byte| byte.is_ascii_alphanumeric()));
+            let first_index = track.indices.iter().next().map(|index| index.number);
+            let indices_valid = matches!(first_index, Some(0 | 1))
+                && track.indices.len() < 256
+                && track
+                    .indices
+                    .iter()
+                    .zip(track.indices.iter().skip(1))
+                    .all(|(index, next)| {
+                        next.number == index.number.wrapping_add(1) && next.offset > index.offset
+                    })
+                && track.indices.iter().all(|index| on_sector(index.offset));
+            let valid = (1..=max_track).contains(&track.number)
+                && isrc_valid
+                && indices_valid
+                && on_sector(track.offset)
+                && previous.is_none_or(|previous| {
+                    previous.offset < track.offset && previous.number < track.number
+                });
+            previous = Some(track);
+            valid
+        });
+        header_valid && cd_valid && tracks_valid
+    }
+
+    pub fn len(&self) -> usize {
+        HEADER_LENGTH + TRACK_LENGTH + self.tracks().map(|track| track.len()).sum::<usize>()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    pub fn write<BS: ByteSink>(&self, sink: &mut BS) {
+        let catalog = self.media_catalog_number.as_bytes();
+        catalog
+            .iter()
+            .chain(&[0; CATALOG_LENGTH][catalog.len()..])
+            .chain(&self.lead_in.to_be_bytes())
+            .for_each(|&byte| sink.write(byte));
+        sink.write(u8::from(self.is_cd) << 7);
+        sink.write_repeated(0, 258);
+        #[allow(clippy::cast_possible_truncation)]
+        sink.write(self.tracks().count() as u8 + 1);
+        for track in self.tracks() {
+            track.write(sink);
+        }
+        let lead_out_number = if self.is_cd { 170 } else { 255 };
+        CueSheetTrack::new(self.lead_out, lead_out_number, &[]).write(sink);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CueSheet, CueSheetIndex, CueSheetTrack};
+    use crate::{metadata::stream_info::StreamInfo, BufferByteSink, Error};
+
+    #[test]
+    fn cd_rules() {
+        let stream_info = StreamInfo::new(44_100, 2, 16);
+        let indices = [
+            CueSheetIndex {
+                offset: 0,
+                number: 0,
+            },
+            CueSheetIndex {
+                offset: 588 * 75,
+                number: 1,
+            },
+        ];
+        let tracks = [
+            CueSheetTrack::new(0, 1, &indices[1..]),
+            CueSheetTrack::new(588 * 1000, 2, &indices),
+        ];
+        let cue_sheet = CueSheet::new(
+            "1234567890123",
+            88_200,
+            true,
+            &tracks,
+            588 * 2000,
+            &stream_info,
+        )
+        .unwrap();
+        let mut sink: BufferByteSink<1024> = BufferByteSink::new();
+        cue_sheet.write(&mut sink);
+        let bytes = sink.as_slice();
+        assert_eq!(bytes.len(), cue_sheet.len());
+        assert_eq!(bytes[128 + 8], 0b1000_0000);
+        assert_eq!(bytes[395], 3);
+        assert_eq!(bytes[bytes.len() - 36 + 8], 170);
+
+        let parsed = CueSheet::from_bytes(bytes).unwrap();
+        assert!(parsed.is_valid(&stream_info));
+        assert!(parsed.tracks().eq(tracks.iter().copied()));
+        assert_eq!(parsed.lead_out(), 588 * 2000);
+        assert_eq!(parsed.media_catalog_number(), "1234567890123");
+
+        let off_sector = [CueSheetTrack::new(1, 1, &indices[1..])];
+        let mono = StreamInfo::new(44_100, 1, 16);
+        assert_eq!(
+            CueSheet::new("", 0, true, &off_sector, 588, &stream_info).err(),
+            Some(Error::InvalidCueSheet)
+        );
+        assert_eq!(
+            CueSheet::new("", 0, true, &tracks, 588 * 2000, &mono).err(),
+            Some(Error::InvalidCueSheet)
+        );
+        assert!(CueSheet::new("", 0, false, &off_sector, 2, &mono).is_ok());
+        assert_eq!(
+            CueSheet::new("", 588, false, &off_sector, 2, &mono).err(),
+            Some(Error::InvalidCueSheet)
+        );
+    }
+
+    #[test]
+    fn non_cd_rules() {
+        let stream_info = StreamInfo::new(48_000, 1, 24);
+        let indices = [CueSheetIndex {
+            offset: 0,
+            number: 1,
+        }];
+        let tracks: [CueSheetTrack; 120] =
+            core::array::from_fn(|i| CueSheetTrack::new(i as u64 * 10, i as u8 + 1, &indices));
+        let cue_sheet = CueSheet::new("", 0, false, &tracks, 1200, &stream_info).unwrap();
+        let mut sink: BufferByteSink<8192> = BufferByteSink::new();
+        cue_sheet.write(&mut sink);
+        let parsed = CueSheet::from_bytes(sink.as_slice()).unwrap();
+        assert!(parsed.is_valid(&stream_info));
+        assert_eq!(parsed.tracks().count(), 120);
+
+        // A track count leaving out the lead-out track.
+        let lead_out_only = CueSheet::new("", 0, false, &[], 0, &stream_info).unwrap();
+        let mut sink: BufferByteSink<512> = BufferByteSink::new();
+        lead_out_only.write(&mut sink);
+        let mut bytes = [0; 432];
+        bytes.copy_from_slice(sink.as_slice());
+        assert!(CueSheet::from_bytes(&bytes).is_ok());
+        bytes[395] = 0;
+        assert_eq!(
+            CueSheet::from_bytes(&bytes).err(),
+            Some(Error::InvalidMetadata)
+        );
+    }
+}
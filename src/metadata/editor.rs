@@ -0,0 +1,254 @@
+use super::{chain::MetadataChain, padding::Padding, reader::MetadataReader, MetaDataBlockType};
+use crate::{ByteStorage, Error};
+
+/// PADDING left after the metadata when the stream had to be rewritten, so
+/// the next edits fit in place.
+pub const REWRITE_PADDING: usize = 4096;
+
+/// Edits the metadata of a FLAC stream kept in a [`ByteStorage`].
+///
+/// The blocks are read into a caller buffer and can then be removed, changed
+/// or added through [`chain`](Self::chain). [`write`](Self::write) only
+/// rewrites the metadata region, growing or shrinking the padding, unless
+/// the blocks no longer fit in it.
+pub struct MetadataEditor<'a, const CAPACITY: usize> {
+    chain: MetadataChain<'a, CAPACITY>,
+    start: u64,
+    region_length: u64,
+}
+
+impl<'a, const CAPACITY: usize> MetadataEditor<'a, CAPACITY> {
+    /// Reads the metadata at the current position of `storage`, keeping the
+    /// payloads in `buffer`. Padding is dropped and recomputed on write.
+    pub fn read<S: ByteStorage>(storage: &mut S, mut buffer: &'a mut [u8]) -> Result<Self, Error> {
+        let start = storage.position();
+        let mut chain = MetadataChain::new();
+        let mut reader = MetadataReader::new(&mut *storage)?;
+        while let Some(header) = reader.next_header()? {
+            if header.block_type == 1 {
+                reader.skip_payload(&header)?;
+                continue;
+            }
+            if buffer.len() < header.length {
+                return Err(Error::BufferTooSmall);
+            }
+            let (payload, rest) = core::mem::take(&mut buffer).split_at_mut(header.length);
+            buffer = rest;
+            let payload = reader.read_payload(&header, payload)?;
+            chain.push(MetaDataBlockType::from_bytes(header.block_type, payload)?)?;
+        }
+        let region_length = storage.position() - start;
+        Ok(Self {
+            chain,
+            start,
+            region_length,
+        })
+    }
+
+    /// The blocks read, padding excluded, to edit in place.
+    pub fn chain(&mut self) -> &mut MetadataChain<'a, CAPACITY> {
+        &mut self.chain
+    }
+
+    /// Writes the edited metadata back, returning whether the audio had to
+    /// be moved.
+    ///
+    /// If the blocks fit in the old metadata region, only that region is
+    /// written and the gap is filled with padding. Otherwise every frame is
+    /// moved forward to make room, and [`REWRITE_PADDING`] bytes of padding
+    /// are left after the blocks.
+    pub fn write<S: ByteStorage>(&mut self, storage: &mut S) -> Result<bool, Error> {
+        self.chain
+            .retain(|block| !matches!(block, MetaDataBlockType::Padding(_)));
+        let length = self.chain.len() as u64;
+        let rewrite = !(length == self.region_length || length + 4 <= self.region_length);
+        let padding = if rewrite {
+            Some(REWRITE_PADDING)
+        } else {
+            let gap = self.region_length - length;
+            #[allow(clippy::cast_possible_truncation)]
+            (gap != 0).then(|| (gap - 4) as usize)
+        };
+        // Everything that can fail is checked before the frames are moved, so
+        // an error leaves the storage as it was.
+        self.chain.check()?;
+        if let Some(padding) = padding {
+            MetaDataBlockType::Padding(Padding::new(padding)).check_len()?;
+            if self.chain.blocks().count() == CAPACITY {
+                return Err(Error::TooManyBlocks);
+            }
+        }
+        if rewrite {
+            let new_region_length = length + 4 + REWRITE_PADDING as u64;
+            self.move_frames(storage, new_region_length)?;
+            self.region_length = new_region_length;
+        }
+        if let Some(padding) = padding {
+            self.chain
+                .push(MetaDataBlockType::Padding(Padding::new(padding)))?;
+        }
+        storage.seek(self.start);
+        let written = self.chain.write(storage);
+        self.chain
+            .retain(|block| !matches!(block, MetaDataBlockType::Padding(_)));
+        written?;
+        Ok(rewrite)
+    }
+
+    /// Moves everything after the metadata region to start
+    /// `new_region_length` bytes after the marker instead, from the end so
+    /// nothing is overwritten before it is copied.
+    #[allow(clippy::cast_possible_truncation)]
+    fn move_frames<S: ByteStorage>(
+        &self,
+        storage: &mut S,
+        new_region_length: u64,
+    ) -> Result<(), Error> {
+        let shift = new_region_length - self.region_length;
+        let frames_start = self.start + self.region_length;
+        let mut end = storage.len();
+        let mut chunk = [0; 256];
+        while end > frames_start {
+            let length = (end - frames_start).min(chunk.len() as u64);
+            let chunk = &mut chunk[..length as usize];
+            storage.seek(end - length);
+            if !storage.read_bytes(chunk) {
+                return Err(Error::UnexpectedEnd);
+            }
+            if end + shift > storage.len() {
+                storage.seek(storage.len());
+                storage.write_repeated(0, (end + shift - storage.len()) as usize);
+            }
+            storage.seek(end - length + shift);
+            storage.write_bytes(chunk);
+            end -= length;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MetadataEditor, REWRITE_PADDING};
+    use crate::{
+        metadata::{
+            chain::MetadataChain,
+            padding::Padding,
+            picture::{Picture, PictureType},
+            reader::MetadataReader,
+            stream_info::StreamInfo,
+            vorbis_comment::VorbisCommentBuffer,
+            MetaDataBlockType,
+        },
+        BufferByteSink, ByteSink, Error, SeekableByteSink, SliceByteSource,
+    };
+
+    const FRAMES: &[u8] = &[0xFF, 0xF8, 1, 2, 3, 4, 5];
+
+    #[test]
+    fn edits_in_padding_then_rewrites() {
+        let mut comment: VorbisCommentBuffer<64> = VorbisCommentBuffer::new("dev").unwrap();
+        comment.add_tag("TITLE", "old").unwrap();
+        let mut chain: MetadataChain<3> = MetadataChain::new();
+        chain
+            .push(MetaDataBlockType::StreamInfo(StreamInfo::new(8_000, 1, 16)))
+            .unwrap()
+            .push(MetaDataBlockType::VorbisComent(comment.block()))
+            .unwrap()
+            .push(MetaDataBlockType::Padding(Padding::new(64)))
+            .unwrap();
+        let mut storage: BufferByteSink<8192> = BufferByteSink::new();
+        chain.write(&mut storage).unwrap();
+        let region_length = storage.as_slice().len();
+        storage.write_bytes(FRAMES);
+
+        storage.seek(0);
+        let mut buffer = [0; 128];
+        let mut editor: MetadataEditor<4> =
+            MetadataEditor::read(&mut storage, &mut buffer).unwrap();
+        let old = editor
+            .chain()
+            .blocks()
+            .find_map(|block| match block {
+                MetaDataBlockType::VorbisComent(comment) => Some(*comment),
+                _ => None,
+            })
+            .unwrap();
+        let mut comment: VorbisCommentBuffer<64> = VorbisCommentBuffer::from_comment(&old).unwrap();
+        comment
+            .remove_tags("title")
+            .add_tag("TITLE", "a longer title")
+            .unwrap();
+        editor
+            .chain()
+            .retain(|block| !matches!(block, MetaDataBlockType::VorbisComent(_)));
+        editor
+            .chain()
+            .push(MetaDataBlockType::VorbisComent(comment.block()))
+            .unwrap();
+        assert!(!editor.write(&mut storage).unwrap());
+        assert_eq!(&storage.as_slice()[region_length..], FRAMES);
+
+        let image = [0; 100];
+        let picture = Picture::new(PictureType::Other, "image/png", "", &image);
+        editor
+            .chain()
+            .push(MetaDataBlockType::Picture(picture))
+            .unwrap();
+        assert!(editor.write(&mut storage).unwrap());
+        let bytes = storage.as_slice();
+        assert_eq!(&bytes[bytes.len() - FRAMES.len()..], FRAMES);
+
+        let mut source = SliceByteSource::new(bytes);
+        let mut reader = MetadataReader::new(&mut source).unwrap();
+        let mut buffer = [0; 256];
+        let mut tags = None;
+        while let Some(block) = reader.next_block(&mut buffer[..]).ok().flatten() {
+            match block.into_block_type() {
+                MetaDataBlockType::VorbisComent(comment) => {
+                    tags = comment.tags().next().map(|(_, value)| value.len());
+                }
+                MetaDataBlockType::Padding(padding) => assert_eq!(padding.len(), REWRITE_PADDING),
+                _ => {}
+            }
+        }
+        assert_eq!(tags, Some("a longer title".len()));
+        assert_eq!(source.remaining(), FRAMES);
+    }
+
+    #[test]
+    fn failed_rewrite_leaves_storage() {
+        let mut chain: MetadataChain<2> = MetadataChain::new();
+        chain
+            .push(MetaDataBlockType::StreamInfo(StreamInfo::new(8_000, 1, 16)))
+            .unwrap()
+            .push(MetaDataBlockType::Padding(Padding::new(8)))
+            .unwrap();
+        let mut storage: BufferByteSink<8192> = BufferByteSink::new();
+        chain.write(&mut storage).unwrap();
+        storage.write_bytes(FRAMES);
+        let mut original = [0; 64];
+        let original = &mut original[..storage.as_slice().len()];
+        original.copy_from_slice(storage.as_slice());
+
+        storage.seek(0);
+        let mut buffer = [0; 64];
+        let mut editor: MetadataEditor<2> =
+            MetadataEditor::read(&mut storage, &mut buffer).unwrap();
+        let image = [0; 100];
+        let picture = Picture::new(PictureType::Other, "image/png", "", &image);
+        editor
+            .chain()
+            .push(MetaDataBlockType::Picture(picture))
+            .unwrap();
+        // No room left in the chain for the padding after the blocks.
+        assert_eq!(editor.write(&mut storage), Err(Error::TooManyBlocks));
+        assert_eq!(storage.as_slice(), &original[..]);
+
+        editor
+            .chain()
+            .retain(|block| !matches!(block, MetaDataBlockType::StreamInfo(_)));
+        assert_eq!(editor.write(&mut storage), Err(Error::MissingStreamInfo));
+        assert_eq!(storage.as_slice(), &original[..]);
+    }
+}
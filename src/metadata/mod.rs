@@ -1,58 +1,153 @@
-use crate::ByteSink;
+use crate::{ByteSink, Error};
 
-pub struct MetaDataBlockHeader<const N: usize> {
+pub struct MetaDataBlockHeader<'a> {
     is_last: bool,
-    block_type: MetaDataBlockType<N>,
+    block_type: MetaDataBlockType<'a>,
 }
 
-impl<const N: usize> MetaDataBlockHeader<N> {
-    pub fn new(is_last: bool, block_type: MetaDataBlockType<N>) -> Self {
+impl<'a> MetaDataBlockHeader<'a> {
+    pub fn new(is_last: bool, block_type: MetaDataBlockType<'a>) -> Self {
         Self {
             is_last,
             block_type,
         }
     }
 
-    pub fn write<BS: ByteSink>(&self, sink: &mut BS) {
-        let last_flag = if self.is_last {
-            0b1000_0000
-        } else {
-            0b0000_0000
-        };
-        sink.write(last_flag | self.block_type.as_byte());
-        self.block_type.write(sink);
+    pub fn is_last(&self) -> bool {
+        self.is_last
+    }
+
+    pub fn block_type(&self) -> &MetaDataBlockType<'a> {
+        &self.block_type
+    }
+
+    pub fn into_block_type(self) -> MetaDataBlockType<'a> {
+        self.block_type
+    }
+
+    /// Writes the block type, the 24-bit payload length and the payload.
+    ///
+    /// Fails without writing anything if the payload does not fit in 24 bits.
+    pub fn write<BS: ByteSink>(&self, sink: &mut BS) -> Result<(), Error> {
+        self.block_type.write_block(self.is_last, sink)
     }
 }
 
-#[repr(u8)]
-pub enum MetaDataBlockType<const N: usize> {
-    StreamInfo(stream_info::StreamInfo) = 0,
-    Padding(padding::Padding<N>) = 1,
-    Application(application::Application<N>) = 2,
-    SeekTable = 3,
-    VorbisComent = 4,
-    CueSheet = 5,
-    Picture = 6,
+/// Marker every native FLAC stream starts with, ahead of the metadata blocks.
+pub const FLAC_MARKER: [u8; 4] = *b"fLaC";
+
+/// Largest payload the 24-bit length field of a block header can describe.
+pub const MAX_BLOCK_LENGTH: usize = 0xFF_FFFF;
+
+pub enum MetaDataBlockType<'a> {
+    StreamInfo(stream_info::StreamInfo),
+    Padding(padding::Padding),
+    Application(application::Application<'a>),
+    SeekTable(seek_table::SeekTable<'a>),
+    VorbisComent(vorbis_comment::VorbisComment<'a>),
+    CueSheet(cue_sheet::CueSheet<'a>),
+    Picture(picture::Picture<'a>),
+    /// A reserved block type, its payload kept as it was read.
+    Unknown {
+        block_type: u8,
+        data: &'a [u8],
+    },
 }
 
-impl<const N: usize> MetaDataBlockType<N> {
-    fn as_byte(&self) -> u8 {
-        unsafe { *<*const _>::from(self).cast::<u8>() }
+impl<'a> MetaDataBlockType<'a> {
+    /// Parses the payload of a block of type `block_type`.
+    pub fn from_bytes(block_type: u8, payload: &'a [u8]) -> Result<Self, Error> {
+        Ok(match block_type {
+            0 => MetaDataBlockType::StreamInfo(stream_info::StreamInfo::from_bytes(payload)?),
+            1 => MetaDataBlockType::Padding(padding::Padding::new(payload.len())),
+            2 => MetaDataBlockType::Application(application::Application::from_bytes(payload)?),
+            3 => MetaDataBlockType::SeekTable(seek_table::SeekTable::from_bytes(payload)?),
+            4 => {
+                MetaDataBlockType::VorbisComent(vorbis_comment::VorbisComment::from_bytes(payload)?)
+            }
+            5 => MetaDataBlockType::CueSheet(cue_sheet::CueSheet::from_bytes(payload)?),
+            6 => MetaDataBlockType::Picture(picture::Picture::from_bytes(payload)?),
+            block_type => MetaDataBlockType::Unknown {
+                block_type,
+                data: payload,
+            },
+        })
+    }
+
+    /// The 7-bit block type written in the block header.
+    pub fn as_byte(&self) -> u8 {
+        match self {
+            MetaDataBlockType::StreamInfo(_) => 0,
+            MetaDataBlockType::Padding(_) => 1,
+            MetaDataBlockType::Application(_) => 2,
+            MetaDataBlockType::SeekTable(_) => 3,
+            MetaDataBlockType::VorbisComent(_) => 4,
+            MetaDataBlockType::CueSheet(_) => 5,
+            MetaDataBlockType::Picture(_) => 6,
+            MetaDataBlockType::Unknown { block_type, .. } => block_type & 0x7F,
+        }
+    }
+
+    /// Payload length in bytes, block header excluded.
+    pub fn len(&self) -> usize {
+        match self {
+            MetaDataBlockType::StreamInfo(_) => stream_info::StreamInfo::LENGTH,
+            MetaDataBlockType::Padding(padding) => padding.len(),
+            MetaDataBlockType::Application(application) => application.len(),
+            MetaDataBlockType::SeekTable(seek_table) => seek_table.len(),
+            MetaDataBlockType::VorbisComent(vorbis_comment) => vorbis_comment.len(),
+            MetaDataBlockType::CueSheet(cue_sheet) => cue_sheet.len(),
+            MetaDataBlockType::Picture(picture) => picture.len(),
+            MetaDataBlockType::Unknown { data, .. } => data.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
+
+    pub(crate) fn check_len(&self) -> Result<[u8; 3], Error> {
+        let length = u32::try_from(self.len()).map_err(|_| Error::BlockTooLarge)?;
+        match length.to_be_bytes() {
+            [0, length @ ..] => Ok(length),
+            _ => Err(Error::BlockTooLarge),
+        }
+    }
+
+    pub(crate) fn write_block<BS: ByteSink>(
+        &self,
+        is_last: bool,
+        sink: &mut BS,
+    ) -> Result<(), Error> {
+        let length = self.check_len()?;
+        let last_flag = if is_last { 0b1000_0000 } else { 0b0000_0000 };
+        sink.write(last_flag | self.as_byte());
+        sink.write_bytes(&length);
+        self.write(sink);
+        Ok(())
+    }
+
     fn write<BS: ByteSink>(&self, sink: &mut BS) {
         match self {
             MetaDataBlockType::StreamInfo(stream_info) => stream_info.write(sink),
             MetaDataBlockType::Padding(padding) => padding.write(sink),
             MetaDataBlockType::Application(application) => application.write(sink),
-            MetaDataBlockType::SeekTable => todo!(),
-            MetaDataBlockType::VorbisComent => todo!(),
-            MetaDataBlockType::CueSheet => todo!(),
-            MetaDataBlockType::Picture => todo!(),
+            MetaDataBlockType::SeekTable(seek_table) => seek_table.write(sink),
+            MetaDataBlockType::VorbisComent(vorbis_comment) => vorbis_comment.write(sink),
+            MetaDataBlockType::CueSheet(cue_sheet) => cue_sheet.write(sink),
+            MetaDataBlockType::Picture(picture) => picture.write(sink),
+            MetaDataBlockType::Unknown { data, .. } => sink.write_bytes(data),
         }
     }
 }
 
-mod application;
-mod padding;
-mod seek_table;
+pub mod application;
+pub mod chain;
+pub mod cue_sheet;
+pub mod editor;
+pub mod padding;
+pub mod picture;
+pub mod reader;
+pub mod seek_table;
 pub mod stream_info;
+pub mod vorbis_comment;
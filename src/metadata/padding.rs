@@ -1,11 +1,24 @@
 use crate::ByteSink;
 
-pub struct Padding<const N: usize>;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Padding {
+    length: usize,
+}
+
+impl Padding {
+    pub fn new(length: usize) -> Self {
+        Self { length }
+    }
+
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
 
-impl<const N: usize> Padding<N> {
     pub fn write<BS: ByteSink>(&self, sink: &mut BS) {
-        for _ in 0..N {
-            sink.write(0);
-        }
+        sink.write_repeated(0, self.length);
     }
 }
@@ -0,0 +1,301 @@
+use crate::{utils::read_be, ByteSink, Error};
+
+/// MIME type marking the picture data as a URL to the image.
+pub const LINKED_MIME_TYPE: &str = "-->";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum PictureType {
+    Other = 0,
+    /// 32x32 PNG only.
+    FileIcon = 1,
+    OtherFileIcon = 2,
+    FrontCover = 3,
+    BackCover = 4,
+    LeafletPage = 5,
+    Media = 6,
+    LeadArtist = 7,
+    Artist = 8,
+    Conductor = 9,
+    Band = 10,
+    Composer = 11,
+    Lyricist = 12,
+    RecordingLocation = 13,
+    DuringRecording = 14,
+    DuringPerformance = 15,
+    ScreenCapture = 16,
+    BrightColoredFish = 17,
+    Illustration = 18,
+    BandLogo = 19,
+    PublisherLogo = 20,
+}
+
+impl PictureType {
+    const ALL: [PictureType; 21] = [
+        PictureType::Other,
+        PictureType::FileIcon,
+        PictureType::OtherFileIcon,
+        PictureType::FrontCover,
+        PictureType::BackCover,
+        PictureType::LeafletPage,
+        PictureType::Media,
+        PictureType::LeadArtist,
+        PictureType::Artist,
+        PictureType::Conductor,
+        PictureType::Band,
+        PictureType::Composer,
+        PictureType::Lyricist,
+        PictureType::RecordingLocation,
+        PictureType::DuringRecording,
+        PictureType::DuringPerformance,
+        PictureType::ScreenCapture,
+        PictureType::BrightColoredFish,
+        PictureType::Illustration,
+        PictureType::BandLogo,
+        PictureType::PublisherLogo,
+    ];
+
+    pub fn from_u32(picture_type: u32) -> Option<Self> {
+        Self::ALL.get(usize::try_from(picture_type).ok()?).copied()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Picture<'a> {
+    pub picture_type: PictureType,
+    pub mime_type: &'a str,
+    pub description: &'a str,
+    pub width: u32,
+    pub height: u32,
+    /// Bits per pixel.
+    pub depth: u32,
+    /// Palette size for indexed images, 0 otherwise.
+    pub colors: u32,
+    pub data: &'a [u8],
+}
+
+impl<'a> Picture<'a> {
+    /// A picture whose dimensions are left unknown, as zeros.
+    pub fn new(
+        picture_type: PictureType,
+        mime_type: &'a str,
+        description: &'a str,
+        data: &'a [u8],
+    ) -> Self {
+        Self {
+            picture_type,
+            mime_type,
+            description,
+            width: 0,
+            height: 0,
+            depth: 0,
+            colors: 0,
+            data,
+        }
+    }
+
+    /// A picture stored elsewhere, `url` pointing to it.
+    pub fn linked(picture_type: PictureType, description: &'a str, url: &'a str) -> Self {
+        Self::new(picture_type, LINKED_MIME_TYPE, description, url.as_bytes())
+    }
+
+    /// A PNG or JPEG picture, with the MIME type, width, height, depth and
+    /// colors read from its header.
+    pub fn from_image(
+        picture_type: PictureType,
+        description: &'a str,
+        data: &'a [u8],
+    ) -> Result<Self, Error> {
+        let (mime_type, info) = if data.starts_with(PNG_SIGNATURE) {
+            ("image/png", png_info(data))
+        } else if data.starts_with(&[0xFF, 0xD8]) {
+            ("image/jpeg", jpeg_info(data))
+        } else {
+            ("", None)
+        };
+        let info = info.ok_or(Error::UnsupportedImage)?;
+        Ok(Self {
+            width: info.width,
+            height: info.height,
+            depth: info.depth,
+            colors: info.colors,
+            ..Self::new(picture_type, mime_type, description, data)
+        })
+    }
+
+    /// Parses a PICTURE payload.
+    pub fn from_bytes(payload: &'a [u8]) -> Result<Self, Error> {
+        fn field(payload: &[u8], at: usize) -> Option<(&[u8], usize)> {
+            let start = at.checked_add(4)?;
+            let end = start.checked_add(usize::try_from(be_u32(payload, at)?).ok()?)?;
+            Some((payload.get(start..end)?, end))
+        }
+        let parse = || {
+            let picture_type = PictureType::from_u32(be_u32(payload, 0)?)?;
+            let (mime_type, position) = field(payload, 4)?;
+            let (description, position) = field(payload, position)?;
+            let (data, end) = field(payload, position + 16)?;
+            if !mime_type.iter().all(|byte| (0x20..=0x7E).contains(byte)) || end != payload.len() {
+                return None;
+            }
+            Some(Self {
+                picture_type,
+                mime_type: core::str::from_utf8(mime_type).ok()?,
+                description: core::str::from_utf8(description).ok()?,
+                width: be_u32(payload, position)?,
+                height: be_u32(payload, position + 4)?,
+                depth: be_u32(payload, position + 8)?,
+                colors: be_u32(payload, position + 12)?,
+                data,
+            })
+        };
+        parse().ok_or(Error::InvalidMetadata)
+    }
+
+    pub fn is_linked(&self) -> bool {
+        self.mime_type == LINKED_MIME_TYPE
+    }
+
+    pub fn len(&self) -> usize {
+        8 * 4 + self.mime_type.len() + self.description.len() + self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn write<BS: ByteSink>(&self, sink: &mut BS) {
+        fn write_field<BS: ByteSink>(sink: &mut BS, field: &[u8]) {
+            sink.write_bytes(&(field.len() as u32).to_be_bytes());
+            sink.write_bytes(field);
+        }
+        (self.picture_type as u32)
+            .to_be_bytes()
+            .iter()
+            .for_each(|&byte| sink.write(byte));
+        write_field(sink, self.mime_type.as_bytes());
+        write_field(sink, self.description.as_bytes());
+        [self.width, self.height, self.depth, self.colors]
+            .iter()
+            .flat_map(|value| value.to_be_bytes())
+            .for_each(|byte| sink.write(byte));
+        write_field(sink, self.data);
+    }
+}
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+struct ImageInfo {
+    width: u32,
+    height: u32,
+    depth: u32,
+    colors: u32,
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn be_u32(bytes: &[u8], at: usize) -> Option<u32> {
+    read_be(bytes, at, 4).map(|value| value as u32)
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn be_u16(bytes: &[u8], at: usize) -> Option<u16> {
+    read_be(bytes, at, 2).map(|value| value as u16)
+}
+
+/// Reads IHDR, and PLTE for indexed images.
+fn png_info(data: &[u8]) -> Option<ImageInfo> {
+    const INDEXED: u8 = 3;
+    let ihdr = PNG_SIGNATURE.len();
+    if data.get(ihdr + 4..ihdr + 8)? != b"IHDR" {
+        return None;
+    }
+    let bit_depth = u32::from(*data.get(ihdr + 16)?);
+    let color_type = *data.get(ihdr + 17)?;
+    let samples = match color_type {
+        0 | INDEXED => 1,
+        2 => 3,
+        4 => 2,
+        6 => 4,
+        _ => return None,
+    };
+    let mut colors = 0;
+    if color_type == INDEXED {
+        let mut chunk = ihdr;
+        loop {
+            let length = be_u32(data, chunk)? as usize;
+            match data.get(chunk + 4..chunk + 8)? {
+                b"PLTE" => break colors = u32::try_from(length / 3).ok()?,
+                b"IDAT" | b"IEND" => return None,
+                _ => chunk = chunk.checked_add(12 + length)?,
+            }
+        }
+    }
+    Some(ImageInfo {
+        width: be_u32(data, ihdr + 8)?,
+        height: be_u32(data, ihdr + 12)?,
+        depth: samples * bit_depth,
+        colors,
+    })
+}
+
+/// Walks the segments up to the first start-of-frame.
+fn jpeg_info(data: &[u8]) -> Option<ImageInfo> {
+    let mut position = 2;
+    loop {
+        if *data.get(position)? != 0xFF {
+            return None;
+        }
+        let marker = *data.get(position + 1)?;
+        match marker {
+            0xFF => position += 1,
+            0x01 | 0xD0..=0xD7 => position += 2,
+            0xC0..=0xCF if !matches!(marker, 0xC4 | 0xC8 | 0xCC) => {
+                let segment = position + 4;
+                let precision = u32::from(*data.get(segment)?);
+                let components = u32::from(*data.get(segment + 5)?);
+                return Some(ImageInfo {
+                    width: u32::from(be_u16(data, segment + 3)?),
+                    height: u32::from(be_u16(data, segment + 1)?),
+                    depth: precision * components,
+                    colors: 0,
+                });
+            }
+            0xD9 | 0xDA => return None,
+            _ => position += 2 + usize::from(be_u16(data, position + 2)?),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Picture, PictureType};
+    use crate::{BufferByteSink, Error};
+
+    #[test]
+    fn image_headers() {
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR\0\0\0\x20\0\0\0\x10\x08\x03\0\0\0\0\0\0\0\
+                    \0\0\0\x06PLTE\0\0\0\xff\xff\xff\0\0\0\0";
+        let picture = Picture::from_image(PictureType::FileIcon, "", png).unwrap();
+        assert_eq!(picture.mime_type, "image/png");
+        assert_eq!((picture.width, picture.height), (32, 16));
+        assert_eq!((picture.depth, picture.colors), (8, 2));
+
+        let jpeg = b"\xff\xd8\xff\xe0\0\x04ab\xff\xc2\0\x11\x08\x01\xe0\x02\x80\x03";
+        let picture = Picture::from_image(PictureType::FrontCover, "", jpeg).unwrap();
+        assert_eq!(picture.mime_type, "image/jpeg");
+        assert_eq!((picture.width, picture.height), (640, 480));
+        assert_eq!((picture.depth, picture.colors), (24, 0));
+
+        assert_eq!(
+            Picture::from_image(PictureType::Other, "", b"GIF89a").err(),
+            Some(Error::UnsupportedImage)
+        );
+
+        let linked = Picture::linked(PictureType::BandLogo, "logo", "http://a/b.png");
+        let mut sink: BufferByteSink<64> = BufferByteSink::new();
+        linked.write(&mut sink);
+        assert_eq!(sink.as_slice().len(), linked.len());
+        assert_eq!(&sink.as_slice()[..11], b"\0\0\0\x13\0\0\0\x03-->");
+    }
+}
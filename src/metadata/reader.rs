@@ -0,0 +1,195 @@
+use super::{padding::Padding, MetaDataBlockHeader, MetaDataBlockType, FLAC_MARKER};
+use crate::{ByteSource, Error};
+
+/// Type, length and last-block flag of a metadata block, read ahead of its
+/// payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockHeader {
+    pub is_last: bool,
+    pub block_type: u8,
+    pub length: usize,
+}
+
+/// Walks the metadata blocks at the start of a native FLAC stream.
+///
+/// Once [`next_block`](Self::next_block) returns `None` the source is left
+/// at the first frame.
+pub struct MetadataReader<S: ByteSource> {
+    source: S,
+    finished: bool,
+}
+
+impl<S: ByteSource> MetadataReader<S> {
+    /// Reads and checks the `fLaC` marker.
+    pub fn new(mut source: S) -> Result<Self, Error> {
+        let mut marker = [0; 4];
+        if !source.read_bytes(&mut marker) {
+            return Err(Error::UnexpectedEnd);
+        }
+        if marker != FLAC_MARKER {
+            return Err(Error::InvalidMetadata);
+        }
+        Ok(Self {
+            source,
+            finished: false,
+        })
+    }
+
+    /// Reads the header of the next block, leaving its payload to
+    /// [`read_payload`](Self::read_payload) or
+    /// [`skip_payload`](Self::skip_payload).
+    pub fn next_header(&mut self) -> Result<Option<BlockHeader>, Error> {
+        if self.finished {
+            return Ok(None);
+        }
+        let mut header = [0; 4];
+        if !self.source.read_bytes(&mut header) {
+            return Err(Error::UnexpectedEnd);
+        }
+        let [flags, length @ ..] = header;
+        self.finished = flags & 0b1000_0000 != 0;
+        Ok(Some(BlockHeader {
+            is_last: self.finished,
+            block_type: flags & 0b0111_1111,
+            length: usize::try_from(u32::from_be_bytes([0, length[0], length[1], length[2]]))
+                .map_err(|_| Error::BlockTooLarge)?,
+        }))
+    }
+
+    /// Reads the payload `header` announced into the start of `buffer`.
+    pub fn read_payload<'b>(
+        &mut self,
+        header: &BlockHeader,
+        buffer: &'b mut [u8],
+    ) -> Result<&'b [u8], Error> {
+        let Some(payload) = buffer.get_mut(..header.length) else {
+            self.skip_payload(header)?;
+            return Err(Error::BufferTooSmall);
+        };
+        if !self.source.read_bytes(payload) {
+            return Err(Error::UnexpectedEnd);
+        }
+        Ok(payload)
+    }
+
+    pub fn skip_payload(&mut self, header: &BlockHeader) -> Result<(), Error> {
+        if self.source.skip(header.length) {
+            Ok(())
+        } else {
+            Err(Error::UnexpectedEnd)
+        }
+    }
+
+    /// Reads the next block into `buffer` and parses it.
+    ///
+    /// A block bigger than `buffer` is skipped and reported as
+    /// [`Error::BufferTooSmall`], so reading can go on with the next one.
+    /// PADDING is skipped without using `buffer`, whatever its length.
+    pub fn next_block<'b>(
+        &mut self,
+        buffer: &'b mut [u8],
+    ) -> Result<Option<MetaDataBlockHeader<'b>>, Error> {
+        let Some(header) = self.next_header()? else {
+            return Ok(None);
+        };
+        if header.block_type == 1 {
+            self.skip_payload(&header)?;
+            let padding = MetaDataBlockType::Padding(Padding::new(header.length));
+            return Ok(Some(MetaDataBlockHeader::new(header.is_last, padding)));
+        }
+        let payload = self.read_payload(&header, buffer)?;
+        let block_type = MetaDataBlockType::from_bytes(header.block_type, payload)?;
+        Ok(Some(MetaDataBlockHeader::new(header.is_last, block_type)))
+    }
+
+    pub fn into_source(self) -> S {
+        self.source
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MetadataReader;
+    use crate::{
+        metadata::{
+            application::{AppId, Application},
+            chain::MetadataChain,
+            padding::Padding,
+            stream_info::StreamInfo,
+            vorbis_comment::VorbisCommentBuffer,
+            MetaDataBlockType,
+        },
+        BufferByteSink, ByteSink, Error, SliceByteSource,
+    };
+
+    #[test]
+    fn reads_back_chain() {
+        let mut stream_info = StreamInfo::new(48_000, 2, 24);
+        stream_info.added_block_with(4096);
+        stream_info.added_frame_with(1234);
+        let mut comment: VorbisCommentBuffer<64> = VorbisCommentBuffer::new("dev").unwrap();
+        comment.add_tag("DEVICE", "42").unwrap();
+        let mut chain: MetadataChain<6> = MetadataChain::new();
+        chain
+            .push(MetaDataBlockType::StreamInfo(stream_info))
+            .unwrap()
+            .push(MetaDataBlockType::VorbisComent(comment.block()))
+            .unwrap()
+            .push(MetaDataBlockType::Application(Application::new(
+                AppId::Xmcd,
+                b"cddb",
+            )))
+            .unwrap()
+            .push(MetaDataBlockType::Unknown {
+                block_type: 9,
+                data: b"raw",
+            })
+            .unwrap()
+            .push(MetaDataBlockType::Unknown {
+                block_type: 10,
+                data: &[0; 100],
+            })
+            .unwrap()
+            .push(MetaDataBlockType::Padding(Padding::new(300)))
+            .unwrap();
+        let mut sink: BufferByteSink<512> = BufferByteSink::new();
+        chain.write(&mut sink).unwrap();
+        sink.write_bytes(&[0xFF, 0xF8]);
+
+        let mut source = SliceByteSource::new(sink.as_slice());
+        let mut reader = MetadataReader::new(&mut source).unwrap();
+        let mut buffer = [0; 64];
+        let block = reader.next_block(&mut buffer).unwrap().unwrap();
+        assert!(
+            matches!(block.block_type(), MetaDataBlockType::StreamInfo(info) if *info == stream_info)
+        );
+        let block = reader.next_block(&mut buffer).unwrap().unwrap();
+        let MetaDataBlockType::VorbisComent(comment) = block.block_type() else {
+            panic!();
+        };
+        assert_eq!(comment.vendor(), "dev");
+        assert!(comment.tags().eq([("DEVICE", "42")]));
+        let block = reader.next_block(&mut buffer).unwrap().unwrap();
+        assert!(
+            matches!(block.block_type(), MetaDataBlockType::Application(application) if application.app_id() == AppId::Xmcd)
+        );
+        let block = reader.next_block(&mut buffer).unwrap().unwrap();
+        assert!(matches!(
+            block.block_type(),
+            MetaDataBlockType::Unknown {
+                block_type: 9,
+                data: b"raw"
+            }
+        ));
+        assert_eq!(
+            reader.next_block(&mut buffer).err(),
+            Some(Error::BufferTooSmall)
+        );
+        let block = reader.next_block(&mut buffer).unwrap().unwrap();
+        assert!(
+            matches!(block.block_type(), MetaDataBlockType::Padding(padding) if padding.len() == 300)
+        );
+        assert!(reader.next_block(&mut buffer).unwrap().is_none());
+        assert_eq!(source.remaining(), &[0xFF, 0xF8]);
+    }
+}
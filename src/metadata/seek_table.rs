@@ -1,5 +1,8 @@
-use crate::ByteSink;
+use crate::{utils::read_be, ByteSink, Error};
 
+/// One entry of a SEEKTABLE: where a frame starts, in samples and in bytes
+/// from the first frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct SeekPoint {
     sample_num_of_first_in_target: u64,
     offset_to_target_frame: u64,
@@ -7,6 +10,48 @@ pub struct SeekPoint {
 }
 
 impl SeekPoint {
+    /// Sample number marking a point as a placeholder.
+    pub const PLACEHOLDER: u64 = 0xFFFF_FFFF_FFFF_FFFF;
+    /// Bytes a point takes in the table.
+    pub const LENGTH: usize = 18;
+
+    pub fn new(
+        sample_num_of_first_in_target: u64,
+        offset_to_target_frame: u64,
+        sample_count_in_target_frame: u16,
+    ) -> Self {
+        Self {
+            sample_num_of_first_in_target,
+            offset_to_target_frame,
+            sample_count_in_target_frame,
+        }
+    }
+
+    /// A point reserving room in the table without pointing anywhere.
+    pub const fn placeholder() -> Self {
+        Self {
+            sample_num_of_first_in_target: Self::PLACEHOLDER,
+            offset_to_target_frame: 0,
+            sample_count_in_target_frame: 0,
+        }
+    }
+
+    pub fn is_placeholder(&self) -> bool {
+        self.sample_num_of_first_in_target == Self::PLACEHOLDER
+    }
+
+    pub fn sample_number(&self) -> u64 {
+        self.sample_num_of_first_in_target
+    }
+
+    pub fn offset(&self) -> u64 {
+        self.offset_to_target_frame
+    }
+
+    pub fn sample_count(&self) -> u16 {
+        self.sample_count_in_target_frame
+    }
+
     pub fn write<BS: ByteSink>(&self, sink: &mut BS) {
         self.sample_num_of_first_in_target
             .to_be_bytes()
@@ -16,3 +61,103 @@ impl SeekPoint {
             .for_each(|&byte| sink.write(byte));
     }
 }
+
+impl Default for SeekPoint {
+    fn default() -> Self {
+        Self::placeholder()
+    }
+}
+
+/// Distance between the samples the encoder places seek points at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekSpacing {
+    Samples(u64),
+    Seconds(u32),
+}
+
+impl SeekSpacing {
+    pub(crate) fn samples(self, sample_rate: u32) -> u64 {
+        match self {
+            SeekSpacing::Samples(samples) => samples,
+            SeekSpacing::Seconds(seconds) => u64::from(seconds) * u64::from(sample_rate),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum SeekPoints<'a> {
+    Points(&'a [SeekPoint]),
+    /// A payload read back, left as it was stored.
+    Raw(&'a [u8]),
+}
+
+#[derive(Clone, Copy)]
+pub struct SeekTable<'a> {
+    points: SeekPoints<'a>,
+}
+
+impl<'a> SeekTable<'a> {
+    /// The points are written as given; see [`normalize`] to put them in the
+    /// order the format requires.
+    pub fn new(points: &'a [SeekPoint]) -> Self {
+        Self {
+            points: SeekPoints::Points(points),
+        }
+    }
+
+    /// Parses a SEEKTABLE payload.
+    pub fn from_bytes(payload: &'a [u8]) -> Result<Self, Error> {
+        if !payload.len().is_multiple_of(SeekPoint::LENGTH) {
+            return Err(Error::InvalidMetadata);
+        }
+        Ok(Self {
+            points: SeekPoints::Raw(payload),
+        })
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn points(&self) -> impl Iterator<Item = SeekPoint> + 'a {
+        let (points, raw): (&[SeekPoint], &[u8]) = match self.points {
+            SeekPoints::Points(points) => (points, &[]),
+            SeekPoints::Raw(raw) => (&[], raw),
+        };
+        points
+            .iter()
+            .copied()
+            .chain(raw.chunks_exact(SeekPoint::LENGTH).map(|point| {
+                let read = |at, length| read_be(point, at, length).unwrap_or_default();
+                SeekPoint::new(read(0, 8), read(8, 8), read(16, 2) as u16)
+            }))
+    }
+
+    pub fn len(&self) -> usize {
+        match self.points {
+            SeekPoints::Points(points) => points.len() * SeekPoint::LENGTH,
+            SeekPoints::Raw(raw) => raw.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn write<BS: ByteSink>(&self, sink: &mut BS) {
+        match self.points {
+            SeekPoints::Points(points) => points.iter().for_each(|point| point.write(sink)),
+            SeekPoints::Raw(raw) => sink.write_bytes(raw),
+        }
+    }
+}
+
+/// Sorts `points` by sample number and turns repeated sample numbers into
+/// placeholders, which end up at the back of the table.
+pub fn normalize(points: &mut [SeekPoint]) {
+    points.sort_unstable_by_key(SeekPoint::sample_number);
+    for i in 1..points.len() {
+        if !points[i].is_placeholder() && points[i].sample_number() == points[i - 1].sample_number()
+        {
+            points[i - 1] = SeekPoint::placeholder();
+        }
+    }
+    points.sort_unstable_by_key(SeekPoint::sample_number);
+}
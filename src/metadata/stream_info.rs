@@ -1,8 +1,8 @@
 use core::cmp::{max, min};
 
-use crate::ByteSink;
+use crate::{utils::read_be, ByteSink, Error};
 
-#[derive(Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct StreamInfo {
     min_block_size: u16,
     max_block_size: u16,
@@ -16,6 +16,9 @@ pub struct StreamInfo {
 }
 
 impl StreamInfo {
+    /// Payload length of a STREAMINFO block.
+    pub const LENGTH: usize = 34;
+
     pub fn new(sample_rate: u32, channels: u8, bits_per_sample: u8) -> Self {
         Self {
             min_block_size: u16::MAX,
@@ -30,28 +33,112 @@ impl StreamInfo {
         }
     }
 
+    /// Sets both block sizes to `block_size`, for a STREAMINFO written once
+    /// ahead of the frames of a fixed-blocksize stream.
+    pub fn with_block_size(mut self, block_size: u16) -> Self {
+        self.min_block_size = block_size;
+        self.max_block_size = block_size;
+        self
+    }
+
+    /// Parses a STREAMINFO payload.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn from_bytes(payload: &[u8]) -> Result<Self, Error> {
+        if payload.len() != Self::LENGTH {
+            return Err(Error::InvalidMetadata);
+        }
+        let read = |at, length| read_be(payload, at, length).unwrap_or_default();
+        let packed = read(10, 8);
+        let mut md5_checksum = [0; 16];
+        md5_checksum.copy_from_slice(&payload[18..]);
+        Ok(Self {
+            min_block_size: read(0, 2) as u16,
+            max_block_size: read(2, 2) as u16,
+            min_frame_size: read(4, 3) as u32,
+            max_frame_size: read(7, 3) as u32,
+            sample_rate: (packed >> 44) as u32,
+            channels: ((packed >> 41) & 0x7) as u8 + 1,
+            bits_per_sample: ((packed >> 36) & 0x1F) as u8 + 1,
+            interchannel_sample_count: packed & 0xF_FFFF_FFFF,
+            md5_checksum: u128::from_be_bytes(md5_checksum),
+        })
+    }
+
     pub fn added_block_with(&mut self, size: u16) {
         self.min_block_size = min(self.min_block_size, size);
         self.max_block_size = max(self.max_block_size, size);
         self.interchannel_sample_count += u64::from(size);
     }
 
+    /// Counts the samples of the short block ending a fixed-blocksize
+    /// stream, which does not count towards the minimum block size.
+    pub fn added_last_block_with(&mut self, size: u16) {
+        self.interchannel_sample_count += u64::from(size);
+    }
+
     pub fn added_frame_with(&mut self, size: u32) {
         self.min_frame_size = min(self.min_frame_size, size);
         self.max_frame_size = max(self.max_frame_size, size);
     }
 
+    /// 0 when unknown.
+    pub fn min_block_size(&self) -> u16 {
+        if self.min_block_size > self.max_block_size {
+            0
+        } else {
+            self.min_block_size
+        }
+    }
+
+    /// 0 when unknown.
+    pub fn max_block_size(&self) -> u16 {
+        self.max_block_size
+    }
 
+    /// 0 when unknown.
+    pub fn min_frame_size(&self) -> u32 {
+        if self.min_frame_size > self.max_frame_size {
+            0
+        } else {
+            self.min_frame_size
+        }
+    }
+
+    /// 0 when unknown.
+    pub fn max_frame_size(&self) -> u32 {
+        self.max_frame_size
+    }
+
+    pub fn md5_checksum(&self) -> u128 {
+        self.md5_checksum
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn channels(&self) -> u8 {
+        self.channels
+    }
+
+    pub fn bits_per_sample(&self) -> u8 {
+        self.bits_per_sample
+    }
+
+    /// Samples per channel added so far.
+    pub fn total_samples(&self) -> u64 {
+        self.interchannel_sample_count
+    }
+
+    /// Writes the payload; the block header and its length come from
+    /// [`MetaDataBlockHeader`](super::MetaDataBlockHeader).
     pub fn write<BS: ByteSink>(&self, sink: &mut BS) {
-        sink.write(0);
-        sink.write(0);
-        sink.write(34);
-        self.min_block_size
+        self.min_block_size()
             .to_be_bytes()
             .iter()
-            .chain(self.max_block_size.to_be_bytes().iter())
-            .chain(self.min_frame_size.to_be_bytes()[1..].iter())
-            .chain(self.max_frame_size.to_be_bytes()[1..].iter())
+            .chain(self.max_block_size().to_be_bytes().iter())
+            .chain(self.min_frame_size().to_be_bytes()[1..].iter())
+            .chain(self.max_frame_size().to_be_bytes()[1..].iter())
             .for_each(|&byte| sink.write(byte));
         sink.write(((self.sample_rate >> 12) & 0xFF) as u8);
         sink.write(((self.sample_rate >> 4) & 0xFF) as u8);
@@ -70,3 +157,30 @@ impl StreamInfo {
             .for_each(|&byte| sink.write(byte));
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::StreamInfo;
+    use crate::BufferByteSink;
+
+    #[test]
+    fn unknown_sizes_are_zero() {
+        let mut stream_info = StreamInfo::new(44_100, 2, 16);
+        let sizes = |info: &StreamInfo| {
+            (
+                info.min_block_size(),
+                info.max_block_size(),
+                info.min_frame_size(),
+                info.max_frame_size(),
+            )
+        };
+        assert_eq!(sizes(&stream_info), (0, 0, 0, 0));
+        let mut sink: BufferByteSink<34> = BufferByteSink::new();
+        stream_info.write(&mut sink);
+        assert_eq!(sink.as_slice()[..10], [0; 10]);
+
+        stream_info.added_block_with(4096);
+        stream_info.added_frame_with(1234);
+        assert_eq!(sizes(&stream_info), (4096, 4096, 1234, 1234));
+    }
+}
@@ -0,0 +1,209 @@
+use crate::{ByteSink, Error};
+
+/// A VORBIS_COMMENT payload, laid out as it is written: the vendor string and
+/// every `NAME=value` tag, each preceded by its little-endian length.
+///
+/// Built with a [`VorbisCommentBuffer`].
+#[derive(Clone, Copy)]
+pub struct VorbisComment<'a> {
+    payload: &'a [u8],
+}
+
+impl<'a> VorbisComment<'a> {
+    /// Parses a VORBIS_COMMENT payload, checking every string is UTF-8 and
+    /// every tag has a `=`.
+    pub fn from_bytes(payload: &'a [u8]) -> Result<Self, Error> {
+        let (_, mut position) = string_at(payload, 0).ok_or(Error::InvalidMetadata)?;
+        let count = le_u32(payload, position).ok_or(Error::InvalidMetadata)?;
+        position += 4;
+        for _ in 0..count {
+            let (tag, next) = string_at(payload, position).ok_or(Error::InvalidMetadata)?;
+            if !tag.contains('=') {
+                return Err(Error::InvalidMetadata);
+            }
+            position = next;
+        }
+        if position != payload.len() {
+            return Err(Error::InvalidMetadata);
+        }
+        Ok(Self { payload })
+    }
+
+    pub fn vendor(&self) -> &'a str {
+        string_at(self.payload, 0).map_or("", |(vendor, _)| vendor)
+    }
+
+    /// Every tag as a `(name, value)` pair, in the order they are stored.
+    pub fn tags(&self) -> impl Iterator<Item = (&'a str, &'a str)> + 'a {
+        let payload = self.payload;
+        let mut position = string_at(payload, 0).map_or(payload.len(), |(_, next)| next + 4);
+        core::iter::from_fn(move || {
+            let (tag, next) = string_at(payload, position)?;
+            position = next;
+            tag.split_once('=')
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.payload.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.payload.is_empty()
+    }
+
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.payload
+    }
+
+    pub fn write<BS: ByteSink>(&self, sink: &mut BS) {
+        sink.write_bytes(self.payload);
+    }
+}
+
+fn le_u32(bytes: &[u8], at: usize) -> Option<u32> {
+    let bytes = bytes.get(at..at.checked_add(4)?)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// The length-prefixed string at `at`, and where the next field starts.
+fn string_at(bytes: &[u8], at: usize) -> Option<(&str, usize)> {
+    let start = at.checked_add(4)?;
+    let end = start.checked_add(usize::try_from(le_u32(bytes, at)?).ok()?)?;
+    let string = core::str::from_utf8(bytes.get(start..end)?).ok()?;
+    Some((string, end))
+}
+
+/// Fixed-capacity storage a VORBIS_COMMENT is assembled in, `N` bytes of
+/// payload at most.
+pub struct VorbisCommentBuffer<const N: usize> {
+    length: usize,
+    count_position: usize,
+    count: u32,
+    buff: [u8; N],
+}
+
+impl<const N: usize> VorbisCommentBuffer<N> {
+    /// Starts a comment with no tags.
+    pub fn new(vendor: &str) -> Result<Self, Error> {
+        let mut comment = Self {
+            length: 0,
+            count_position: 0,
+            count: 0,
+            buff: [0; N],
+        };
+        comment.push_string(&[vendor.as_bytes()])?;
+        comment.count_position = comment.length;
+        comment.push(&0_u32.to_le_bytes())?;
+        Ok(comment)
+    }
+
+    /// Starts from a copy of `comment`, to edit the tags of an existing
+    /// stream.
+    pub fn from_comment(comment: &VorbisComment) -> Result<Self, Error> {
+        let mut buffer = Self::new("")?;
+        buffer.length = 0;
+        buffer.push(comment.as_bytes())?;
+        buffer.count_position = 4 + comment.vendor().len();
+        #[allow(clippy::cast_possible_truncation)]
+        {
+            buffer.count = comment.tags().count() as u32;
+        }
+        Ok(buffer)
+    }
+
+    /// Removes every tag called `name`, ignoring ASCII case as field names
+    /// do.
+    pub fn remove_tags(&mut self, name: &str) -> &mut Self {
+        let mut read = self.count_position + 4;
+        let mut write = read;
+        let mut count = 0;
+        while let Some((tag, next)) = string_at(&self.buff[..self.length], read) {
+            let tag_name = tag.split_once('=').map_or(tag, |(tag_name, _)| tag_name);
+            if !tag_name.eq_ignore_ascii_case(name) {
+                self.buff.copy_within(read..next, write);
+                write += next - read;
+                count += 1;
+            }
+            read = next;
+        }
+        self.length = write;
+        self.count = count;
+        self.buff[self.count_position..self.count_position + 4]
+            .copy_from_slice(&self.count.to_le_bytes());
+        self
+    }
+
+    /// Appends `name=value`.
+    ///
+    /// `name` must be printable ASCII other than `=`. Fails without changing
+    /// the comment if it is not, or if the buffer is full.
+    pub fn add_tag(&mut self, name: &str, value: &str) -> Result<&mut Self, Error> {
+        if name.is_empty()
+            || !name
+                .bytes()
+                .all(|byte| (0x20..=0x7D).contains(&byte) && byte != b'=')
+        {
+            return Err(Error::InvalidFieldName);
+        }
+        self.push_string(&[name.as_bytes(), b"=", value.as_bytes()])?;
+        self.count += 1;
+        self.buff[self.count_position..self.count_position + 4]
+            .copy_from_slice(&self.count.to_le_bytes());
+        Ok(self)
+    }
+
+    pub fn block(&self) -> VorbisComment<'_> {
+        VorbisComment {
+            payload: &self.buff[..self.length],
+        }
+    }
+
+    /// Writes the length of `parts` put together, then the parts.
+    fn push_string(&mut self, parts: &[&[u8]]) -> Result<(), Error> {
+        let length: usize = parts.iter().map(|part| part.len()).sum();
+        let length = u32::try_from(length).map_err(|_| Error::BufferTooSmall)?;
+        if self.buff.len() - self.length < 4 + length as usize {
+            return Err(Error::BufferTooSmall);
+        }
+        self.push(&length.to_le_bytes())?;
+        for part in parts {
+            self.push(part)?;
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let end = self.length + bytes.len();
+        self.buff
+            .get_mut(self.length..end)
+            .ok_or(Error::BufferTooSmall)?
+            .copy_from_slice(bytes);
+        self.length = end;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::VorbisCommentBuffer;
+    use crate::Error;
+
+    #[test]
+    fn tags_and_counts() {
+        let mut comment: VorbisCommentBuffer<40> = VorbisCommentBuffer::new("fw").unwrap();
+        comment.add_tag("DEVICE", "7").unwrap();
+        assert_eq!(
+            comment.add_tag("A=B", "x").err(),
+            Some(Error::InvalidFieldName)
+        );
+        assert_eq!(
+            comment.add_tag("DATE", "2024-01-01T00").err(),
+            Some(Error::BufferTooSmall)
+        );
+        assert_eq!(
+            comment.block().as_bytes(),
+            b"\x02\x00\x00\x00fw\x01\x00\x00\x00\x08\x00\x00\x00DEVICE=7"
+        );
+    }
+}
@@ -1,3 +1,5 @@
+use crate::ByteSink;
+
 pub struct BitIterator<I: Iterator<Item = u8>> {
     iter: I,
     first: Option<u8>,
@@ -145,9 +147,206 @@ pub fn crc16_remainder(bit_stream: &[u8], crc_polynomial: u16, initial: u16) ->
     res
 }
 
+pub fn crc16_update(crc: u16, byte: u8, crc_polynomial: u16) -> u16 {
+    let mut res = crc ^ (u16::from(byte) << 8);
+    for _ in 0..8 {
+        let msb = res & 0b1000_0000_0000_0000;
+        res <<= 1;
+        if msb == 0b1000_0000_0000_0000 {
+            res ^= crc_polynomial;
+        }
+    }
+    res
+}
+
+/// CRC-32 without reflection, as Ogg pages use it.
+pub fn crc32_update(crc: u32, byte: u8, crc_polynomial: u32) -> u32 {
+    let mut res = crc ^ (u32::from(byte) << 24);
+    for _ in 0..8 {
+        let msb = res & 0x8000_0000;
+        res <<= 1;
+        if msb == 0x8000_0000 {
+            res ^= crc_polynomial;
+        }
+    }
+    res
+}
+
+/// Reads `length` bytes, 8 at most, at `at` as a big-endian number.
+pub fn read_be(bytes: &[u8], at: usize, length: usize) -> Option<u64> {
+    let bytes = bytes.get(at..at.checked_add(length)?)?;
+    Some(
+        bytes
+            .iter()
+            .fold(0, |value, &byte| (value << 8) | u64::from(byte)),
+    )
+}
+
+/// Forwards every byte to `sink` while keeping the running CRC-16 and length
+/// of everything written, so a frame can be checksummed without buffering it.
+pub struct Crc16Sink<'a, BS: ByteSink> {
+    crc: u16,
+    length: usize,
+    sink: &'a mut BS,
+}
+
+impl<'a, BS: ByteSink> Crc16Sink<'a, BS> {
+    const CRC_POLYNOMIAL: u16 = 0b1000_0000_0000_0101;
+
+    pub fn new(sink: &'a mut BS) -> Self {
+        Self {
+            crc: 0,
+            length: 0,
+            sink,
+        }
+    }
+
+    pub fn crc(&self) -> u16 {
+        self.crc
+    }
+
+    pub fn length(&self) -> usize {
+        self.length
+    }
+}
+
+impl<BS: ByteSink> ByteSink for Crc16Sink<'_, BS> {
+    fn write(&mut self, next_byte: u8) {
+        self.crc = crc16_update(self.crc, next_byte, Self::CRC_POLYNOMIAL);
+        self.length += 1;
+        self.sink.write(next_byte);
+    }
+}
+
+/// Reads the fields a [`BitSink`](crate::BitSink) writes, most significant
+/// bit first. `None` means the data ended, or for coded numbers was not
+/// valid.
+pub trait BitSource {
+    /// Reads `num_bits` bits, at most 32, into the low bits of the result.
+    fn read_bits(&mut self, num_bits: u8) -> Option<u32>;
+
+    /// Bits read so far.
+    fn position(&self) -> usize;
+
+    /// Skips to the next byte boundary.
+    fn align(&mut self);
+
+    fn is_aligned(&self) -> bool {
+        self.position().is_multiple_of(8)
+    }
+
+    /// Reads a two's complement field, 0 bits reading as 0.
+    #[allow(clippy::cast_possible_wrap)]
+    fn read_signed(&mut self, num_bits: u8) -> Option<i32> {
+        if num_bits == 0 {
+            return Some(0);
+        }
+        let shift = 32 - u32::from(num_bits);
+        Some(((self.read_bits(num_bits)? << shift) as i32) >> shift)
+    }
+
+    /// Counts the zeros before the next one.
+    fn read_unary(&mut self) -> Option<u32> {
+        let mut zeros = 0;
+        while self.read_bits(1)? == 0 {
+            zeros += 1;
+        }
+        Some(zeros)
+    }
+
+    /// Reads a Rice-coded residual with parameter `rice`. The extremes of
+    /// `i32` fold to 33 bits.
+    #[allow(clippy::cast_possible_wrap)]
+    fn read_rice(&mut self, rice: u8) -> Option<i32> {
+        let high = self.read_unary()?;
+        let folded = (u64::from(high) << rice) | u64::from(self.read_bits(rice)?);
+        i32::try_from((folded >> 1) as i64 ^ -((folded & 1) as i64)).ok()
+    }
+
+    /// Reads a frame or sample number in the UTF-8-like coding of frame
+    /// headers, up to 7 bytes for 36 bits.
+    fn read_coded_number(&mut self) -> Option<u64> {
+        let first = self.read_bits(8)?;
+        let extra = match (first << 24).leading_ones() {
+            0 => return Some(u64::from(first)),
+            length @ 2..=7 => length - 1,
+            _ => return None,
+        };
+        let mut number = u64::from(first & (0x7F >> (extra + 1)));
+        for _ in 0..extra {
+            let byte = self.read_bits(8)?;
+            if byte & 0b1100_0000 != 0b1000_0000 {
+                return None;
+            }
+            number = (number << 6) | u64::from(byte & 0b0011_1111);
+        }
+        Some(number)
+    }
+}
+
+pub struct SliceBitSource<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> SliceBitSource<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, position: 0 }
+    }
+
+    /// The whole slice, including what was already read.
+    pub fn bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    /// Bytes read so far, counting a partly read byte.
+    pub fn byte_position(&self) -> usize {
+        self.position.div_ceil(8)
+    }
+}
+
+impl BitSource for SliceBitSource<'_> {
+    #[allow(clippy::cast_possible_truncation)]
+    fn read_bits(&mut self, num_bits: u8) -> Option<u32> {
+        assert!(num_bits <= 32);
+        let end = self.position + usize::from(num_bits);
+        if end > self.bytes.len() * 8 {
+            return None;
+        }
+        let mut value = 0_u64;
+        while self.position < end {
+            let byte = self.bytes[self.position / 8];
+            let offset = self.position % 8;
+            let take = (end - self.position).min(8 - offset);
+            let chunk = (byte << offset) >> (8 - take);
+            value = (value << take) | u64::from(chunk);
+            self.position += take;
+        }
+        Some(value as u32)
+    }
+
+    fn position(&self) -> usize {
+        self.position
+    }
+
+    fn align(&mut self) {
+        self.position = self.byte_position() * 8;
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{crc16_remainder, crc8_remainder, BitIter, BitIter16};
+    use super::{
+        crc16_remainder, crc16_update, crc32_update, crc8_remainder, BitIter, BitIter16, BitSource,
+        SliceBitSource,
+    };
+    use crate::{
+        frames::{
+            header::{ChannelBits, FrameHeader},
+            sub_frame::{residual_codeing, RiceParams},
+        },
+        BitSink, BitSinkAdapter, BufferByteSink,
+    };
 
     #[test]
     fn bit_iter() {
@@ -201,5 +400,57 @@ mod tests {
         let slice: [u8; 8] = [0xAF, 0x72, 0x3C, 0x4C, 0x1E, 0x06, 0xC9, 0xA0];
         let crc = crc16_remainder(&slice, 32773, 0);
         assert_eq!(crc, 0x9753);
+        let crc = slice
+            .iter()
+            .fold(0, |crc, &byte| crc16_update(crc, byte, 32773));
+        assert_eq!(crc, 0x9753);
+    }
+
+    #[test]
+    fn crc32() {
+        let crc = b"123456789"
+            .iter()
+            .fold(0, |crc, &byte| crc32_update(crc, byte, 0x04C1_1DB7));
+        assert_eq!(crc, 0x89A1_897F);
+    }
+
+    #[test]
+    fn bit_source() {
+        let mut sink: BufferByteSink<32> = BufferByteSink::new();
+        {
+            let mut bits = BitSinkAdapter::new(&mut sink);
+            bits.write(0b101, 3);
+            bits.write(0b1111_1110, 8);
+            bits.write(0, 4);
+            bits.write(1, 1);
+            bits.write(0b01_1011, 6);
+        }
+        let mut source = SliceBitSource::new(sink.as_slice());
+        assert_eq!(source.read_bits(3), Some(0b101));
+        assert_eq!(source.read_signed(8), Some(-2));
+        assert_eq!(source.read_unary(), Some(4));
+        assert_eq!(source.position(), 16);
+        assert_eq!(source.read_rice(2), Some(3));
+        assert!(!source.is_aligned());
+        source.align();
+        assert_eq!((source.position(), source.read_bits(1)), (24, None));
+
+        let mut sink: BufferByteSink<16> = BufferByteSink::new();
+        {
+            let mut bits = BitSinkAdapter::new(&mut sink);
+            residual_codeing(&RiceParams::Param(29), &mut bits, i32::MIN);
+            residual_codeing(&RiceParams::Param(29), &mut bits, i32::MAX);
+        }
+        let mut source = SliceBitSource::new(sink.as_slice());
+        assert_eq!(source.read_rice(29), Some(i32::MIN));
+        assert_eq!(source.read_rice(29), Some(i32::MAX));
+
+        let mut sink: BufferByteSink<16> = BufferByteSink::new();
+        FrameHeader::new_variable_size(192, 44_100, ChannelBits::Mono, 16, 0x8_1234_5678)
+            .write(&mut sink);
+        let mut source = SliceBitSource::new(&sink.as_slice()[4..]);
+        assert_eq!(source.read_coded_number(), Some(0x8_1234_5678));
+        assert_eq!(SliceBitSource::new(&[0xFF]).read_coded_number(), None);
+        assert_eq!(SliceBitSource::new(&[0xC2, 0x41]).read_coded_number(), None);
     }
 }